@@ -18,6 +18,9 @@ MAX_N_ALLOFS_PER_COMMIT = 100 # maximum number of allofs to include in the syste
 MAX_INSTANCES_PER_S_CALL = 100 # maximum instance count per service call
 MAX_N_COMMITS = 1000
 
+# emit requests as a quantity of units instead of one entry per instance id
+USE_QUANTITIES = '--quantities' in sys.argv
+
 def build_commitments(n_commits, s_calls):
     '''
     Commitments
@@ -70,6 +73,21 @@ def build_allof(service_calls):
             instances.append([instance])
     
 
+    cost_ceil = np.random.randint(-COST_CEIL_MAX, COST_CEIL_MAX)
+
+    if USE_QUANTITIES:
+        quantities = [
+            {
+                'service': str(call_id),
+                # one unit per instance: a request asks for several, an
+                # offer provides its single instance
+                'quantity': len(instances[i]),
+                'side': 'request' if is_requests[i] else 'offer'
+            }
+            for i, call_id in enumerate(included_calls)
+        ]
+        return {'s_calls': [], 'quantities': quantities, 'cost_ceil': cost_ceil}
+
     allof_entries = []
 
     for i, call_id in enumerate(included_calls):
@@ -84,8 +102,6 @@ def build_allof(service_calls):
                 )
             )
 
-    cost_ceil = np.random.randint(-COST_CEIL_MAX, COST_CEIL_MAX)
-
     return {'s_calls': allof_entries, 'cost_ceil': cost_ceil}
 
 if __name__ == '__main__':
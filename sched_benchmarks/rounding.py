@@ -21,6 +21,9 @@
 Rounding
 """
 
+import json
+import sys
+
 from sklearn.cluster import KMeans
 import matplotlib
 
@@ -146,6 +149,46 @@ def get_new_cluster(new_amt, centers):
     return cl_id
 
 
+def price_levels(centers):
+    """
+    cluster centers as the `levels` of a price in the scheduler's market
+    input: decimal strings, whole micro-units, ascending
+    """
+    minor = np.unique(np.round(np.asarray(centers).reshape(-1) * AMT).astype(np.int64))
+    return [format_amt(int(m)) for m in minor]
+
+
+def format_amt(minor):
+    """
+    decimal string of `minor` micro-units; the sign goes in front, divmod of a
+    negative amount would floor the units instead
+    """
+    sign = "-" if minor < 0 else ""
+    return sign + "%d.%06d" % divmod(abs(minor), AMT)
+
+
+def add_price_levels(filename, regid, centers):
+    """
+    write the cluster centers as `prices.<regid>.levels` of the scheduler
+    document in `filename`, upgrading a legacy array of commits to a versioned
+    document
+    """
+    with open(filename) as handle:
+        document = json.load(handle)
+
+    if isinstance(document, list):
+        document = {
+            "version": 1,
+            "commits": [{"allofs": allofs} for allofs in document],
+        }
+
+    bounds = document.setdefault("prices", {}).setdefault(regid, {})
+    bounds["levels"] = price_levels(centers)
+
+    with open(filename, "w") as handle:
+        json.dump(document, handle, indent=4)
+
+
 def metric(amts, clusters):
     cluster_amts = np.zeros_like(amts)
     denominators = np.zeros_like(amts)
@@ -163,6 +206,12 @@ def metric(amts, clusters):
 if __name__ == "__main__":
     amts = generate_amts()
 
+    # rounding.py --levels <document> <regid>
+    if sys.argv[1:2] == ["--levels"]:
+        centers, _, _ = cluster_amts(amts)
+        add_price_levels(sys.argv[2], sys.argv[3], centers)
+        sys.exit(0)
+
     centers, labels, attrs = cluster_amts(amts)
 
     for i in range(N_ROUNDS):
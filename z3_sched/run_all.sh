@@ -17,13 +17,13 @@
 # OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 # SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-time target/debug/json_sched $1/0.json
-time target/debug/json_sched $1/1.json
-time target/debug/json_sched $1/2.json
-time target/debug/json_sched $1/3.json
-time target/debug/json_sched $1/4.json
-time target/debug/json_sched $1/5.json
-time target/debug/json_sched $1/6.json
-time target/debug/json_sched $1/7.json
-time target/debug/json_sched $1/8.json
-time target/debug/json_sched $1/9.json
\ No newline at end of file
+time target/debug/json_sched $1/0.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/1.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/2.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/3.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/4.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/5.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/6.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/7.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/8.json --commits 11 "${@:2}"
+time target/debug/json_sched $1/9.json --commits 11 "${@:2}"
\ No newline at end of file
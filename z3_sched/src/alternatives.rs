@@ -0,0 +1,265 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Enumerating the schedules that are as good as the optimum, or nearly so.
+//!
+//! Two schedules are distinct when they schedule different commitments or
+//! choose a different allof for one; schedules differing only in matches or
+//! prices count as one.
+
+use std::time::Instant;
+
+use z3::*;
+
+use error::ScheduleError;
+use ids::{AllOfId, CommitId};
+use money::Fraction;
+use options::ScheduleOptions;
+use schedule::{Schedule, Status, UnknownReason};
+use {build_optimizer, encode, run_optimizer, Commit, Encoding, Market};
+
+/// Result of `enumerate_schedules`
+#[derive(Debug, Clone, Serialize)]
+pub struct Alternatives {
+    /// best first
+    pub schedules: Vec<Schedule>,
+
+    /// set if a limit stopped the enumeration before `limit` schedules were
+    /// found or the remaining ones were ruled out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interrupted: Option<UnknownReason>,
+}
+
+/// Up to `limit` distinct schedules, best first, whose primary objective is
+/// within `tolerance` of the optimum's and whose other objectives are at
+/// least as good as the optimum's. A zero tolerance returns the schedules
+/// tied with the optimum on every objective.
+///
+/// `tolerance` is in the unit of the primary objective: the weighted count of
+/// scheduled commitments in the lowest tier for `Objective::Commitments` and
+/// `Objective::CommitmentsThenSurplus`, minor units of `Money` for
+/// `Objective::Surplus`. `options.tie_break` is ignored: a tie-breaking
+/// objective would rank every schedule tied with the optimum below it,
+/// leaving no alternatives at zero tolerance.
+///
+/// Every schedule found is blocked and the optimizer is run again, so the
+/// enumeration ends early when no further schedule is near enough. It is
+/// empty if no schedule exists. `options.timeout` bounds the enumeration as a
+/// whole, `options.rlimit` each run of the optimizer. A run cut short by
+/// either ends the enumeration; the best schedule it found, if that is near
+/// enough and distinct, is kept last with its `Unknown` status, since it is
+/// not known to be the best of the rest.
+pub fn enumerate_schedules(
+    commits: &[Commit],
+    market: &Market,
+    options: &ScheduleOptions,
+    limit: usize,
+    tolerance: Fraction,
+) -> Result<Alternatives, ScheduleError> {
+    if tolerance < Fraction::from_integer(0) {
+        return Err(ScheduleError::InvalidInput(format!(
+            "tolerance {} is negative",
+            tolerance
+        )));
+    }
+
+    let started = Instant::now();
+    let mut alternatives = Alternatives {
+        schedules: Vec::new(),
+        interrupted: None,
+    };
+    while alternatives.schedules.len() < limit {
+        // the limits are part of the context, so each run gets a context of
+        // its own with what is left of the timeout
+        let mut run = options.clone();
+        if let Some(timeout) = options.timeout {
+            match timeout.checked_sub(started.elapsed()) {
+                Some(left) if left.as_millis() > 0 => run.timeout = Some(left),
+                _ => {
+                    alternatives.interrupted = Some(UnknownReason::Timeout);
+                    break;
+                }
+            }
+        }
+
+        let cfg = run.config();
+        let ctx = Context::new(&cfg);
+        let mut encoding = encode(&ctx, commits, market)?;
+        encoding.objectives = encoding.objectives_for(options.objective);
+        let solver = build_optimizer(&ctx, &encoding);
+
+        let mut near = Vec::new();
+        if let Some(optimum) = alternatives.schedules.first() {
+            // the first schedule is the optimum, keep the rest near it
+            let bounds = encoding.objectives.iter().zip(&optimum.objective);
+            for (i, (objective, value)) in bounds.enumerate() {
+                let mut bound = encoding.constant(*value);
+                if i == 0 {
+                    bound = bound.sub(&[&encoding.constant(tolerance)]);
+                }
+                near.push(objective.ge(&bound));
+            }
+        }
+        for found in &alternatives.schedules {
+            near.push(differs_from(&ctx, &encoding, found));
+        }
+        for constraint in &near {
+            solver.assert(constraint);
+        }
+
+        let schedule = run_optimizer(&solver, &encoding, &run)?;
+        match schedule.status {
+            Status::Sat => {}
+            Status::Unsat => break,
+            Status::Unknown { reason } => {
+                alternatives.interrupted = Some(reason);
+                // a best schedule so far has its objective values, and the
+                // model it came from has to keep to the bounds as well
+                if !schedule.objective.is_empty() && holds(&solver.get_model(), &near) {
+                    alternatives.schedules.push(schedule);
+                }
+                break;
+            }
+        }
+
+        alternatives.schedules.push(schedule);
+    }
+
+    Ok(alternatives)
+}
+
+/// Every one of `constraints` holds in `model`
+fn holds<'ctx>(model: &Model<'ctx>, constraints: &[Ast<'ctx>]) -> bool {
+    constraints
+        .iter()
+        .all(|constraint| model.eval(constraint).and_then(|v| v.as_bool()) == Some(true))
+}
+
+/// Some commitment or allof is scheduled differently than in `schedule`
+fn differs_from<'ctx>(
+    ctx: &'ctx Context,
+    encoding: &Encoding<'ctx>,
+    schedule: &Schedule,
+) -> Ast<'ctx> {
+    let mut changes = Vec::new();
+
+    let mut commit_ids: Vec<&CommitId> = encoding.commit_bools.keys().collect();
+    commit_ids.sort();
+    for commit_id in commit_ids {
+        let scheduled = schedule.commits.contains(commit_id);
+        changes.push(encoding.commit_bools[commit_id]._eq(&ctx.from_bool(!scheduled)));
+    }
+
+    let mut allof_ids: Vec<&AllOfId> = encoding.allof_bools.keys().collect();
+    allof_ids.sort();
+    for allof_id in allof_ids {
+        let chosen = schedule.allofs.get(&allof_id.commit) == Some(&allof_id.allof);
+        changes.push(encoding.allof_bools[allof_id]._eq(&ctx.from_bool(!chosen)));
+    }
+
+    let changes: Vec<&Ast<'ctx>> = changes.iter().collect();
+    changes[0].or(&changes[1..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use money::Money;
+    use options::Objective;
+    use tests::{allof, commit};
+
+    /// a buyer and two sellers, the second asking for much more
+    fn sellers() -> Vec<Commit> {
+        vec![
+            commit(vec![allof(&[("abcde-0", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", false)], -10)]),
+            commit(vec![allof(&[("abcde-0", false)], -60)]),
+        ]
+    }
+
+    fn enumerate(objective: Objective, tolerance: i64) -> Alternatives {
+        let options = ScheduleOptions {
+            objective,
+            ..Default::default()
+        };
+        let tolerance = Fraction::from_integer(tolerance);
+        enumerate_schedules(&sellers(), &Market::default(), &options, 5, tolerance).unwrap()
+    }
+
+    fn commits_of(alternatives: &Alternatives) -> Vec<Vec<CommitId>> {
+        alternatives
+            .schedules
+            .iter()
+            .map(|schedule| schedule.commits.clone())
+            .collect()
+    }
+
+    #[test]
+    fn alternatives_keep_every_objective_at_the_optimum() {
+        // either seller schedules two commitments
+        let tied = enumerate(Objective::Commitments, 0);
+        assert_eq!(tied.interrupted, None);
+        let mut found = commits_of(&tied);
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                vec![CommitId(0), CommitId(1)],
+                vec![CommitId(0), CommitId(2)]
+            ]
+        );
+
+        // but only the cheaper one is as good on surplus
+        let tied = enumerate(Objective::CommitmentsThenSurplus, 0);
+        assert_eq!(commits_of(&tied), vec![vec![CommitId(0), CommitId(1)]]);
+
+        // unless the tolerance admits the dearer one, best first
+        let near = enumerate(Objective::Surplus, Money::from(50).minor());
+        assert_eq!(
+            commits_of(&near),
+            vec![
+                vec![CommitId(0), CommitId(1)],
+                vec![CommitId(0), CommitId(2)]
+            ]
+        );
+    }
+
+    #[test]
+    fn an_interrupted_enumeration_keeps_its_best_so_far_as_unknown() {
+        let options = ScheduleOptions {
+            rlimit: Some(1),
+            ..Default::default()
+        };
+        let alternatives = enumerate_schedules(
+            &sellers(),
+            &Market::default(),
+            &options,
+            5,
+            Fraction::from_integer(0),
+        )
+        .unwrap();
+
+        let reason = UnknownReason::ResourceLimit;
+        assert_eq!(alternatives.interrupted, Some(reason));
+        assert!(alternatives
+            .schedules
+            .iter()
+            .all(|schedule| schedule.status == Status::Unknown { reason }));
+    }
+}
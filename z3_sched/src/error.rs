@@ -0,0 +1,113 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use serde_json;
+
+/// Everything that can go wrong between reading commitments and reading back a
+/// schedule
+#[derive(Debug)]
+pub enum ScheduleError {
+    /// the commitments themselves are malformed (empty commit, empty allof, ...)
+    InvalidInput(String),
+
+    /// the encoding could not be built or a model value could not be read back
+    Encoding(String),
+
+    /// the solver gave up before deciding the system
+    SolverUnknown(String),
+
+    Io(io::Error),
+
+    /// the input is not json or does not match the commitment schema; `path`
+    /// points at the offending field when it is known
+    Parse {
+        path: Option<String>,
+        err: serde_json::Error,
+    },
+}
+
+impl ScheduleError {
+    /// Process exit code the binaries use for this error
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ScheduleError::Io(_) => 2,
+            ScheduleError::Parse { .. } => 3,
+            ScheduleError::InvalidInput(_) => 4,
+            ScheduleError::Encoding(_) => 5,
+            ScheduleError::SolverUnknown(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScheduleError::InvalidInput(ref msg) => write!(f, "invalid input: {}", msg),
+            ScheduleError::Encoding(ref msg) => write!(f, "encoding error: {}", msg),
+            ScheduleError::SolverUnknown(ref reason) => {
+                write!(f, "solver returned unknown: {}", reason)
+            }
+            ScheduleError::Io(ref err) => write!(f, "i/o error: {}", err),
+            ScheduleError::Parse {
+                path: Some(ref path),
+                ref err,
+            } => write!(f, "parse error at {}: {}", path, err),
+            ScheduleError::Parse {
+                path: None,
+                ref err,
+            } => write!(f, "parse error: {}", err),
+        }
+    }
+}
+
+impl Error for ScheduleError {
+    fn description(&self) -> &str {
+        match *self {
+            ScheduleError::InvalidInput(_) => "invalid input",
+            ScheduleError::Encoding(_) => "encoding error",
+            ScheduleError::SolverUnknown(_) => "solver returned unknown",
+            ScheduleError::Io(_) => "i/o error",
+            ScheduleError::Parse { .. } => "parse error",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            ScheduleError::Io(ref err) => Some(err),
+            ScheduleError::Parse { ref err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScheduleError {
+    fn from(err: io::Error) -> ScheduleError {
+        ScheduleError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ScheduleError {
+    fn from(err: serde_json::Error) -> ScheduleError {
+        ScheduleError::Parse { path: None, err }
+    }
+}
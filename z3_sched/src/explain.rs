@@ -0,0 +1,350 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt;
+
+use z3::*;
+
+use error::ScheduleError;
+use ids::{CommitId, Constraint, VarId};
+use options::ScheduleOptions;
+use schedule::{Schedule, Status, UnknownReason};
+use {build_optimizer, encode, run_optimizer, Commit, Encoding, Market};
+
+/// A set of constraints that can not all hold at once. Dropping any one of
+/// them makes the rest satisfiable.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnsatCore {
+    pub constraints: Vec<Constraint>,
+}
+
+impl fmt::Display for UnsatCore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "no schedule exists because:")?;
+        for constraint in &self.constraints {
+            writeln!(f, "  {}", constraint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hard constraints guarded by named assumption literals. A constraint only
+/// has to hold when its literal is assumed.
+pub struct Tracked<'ctx> {
+    /// `literal implies constraint` for every constraint
+    pub guards: Vec<Ast<'ctx>>,
+    pub literals: Vec<(Constraint, Ast<'ctx>)>,
+}
+
+/// Guards every constraint of `encoding` with a fresh assumption literal
+pub fn track<'ctx>(ctx: &'ctx Context, encoding: &mut Encoding<'ctx>) -> Tracked<'ctx> {
+    let mut guards = Vec::new();
+    let mut literals = Vec::new();
+
+    for (tag, constraint) in &encoding.constraints {
+        let literal_name = encoding.names.name(VarId::Assumption(tag.clone()));
+        let literal = ctx.named_bool_const(literal_name.as_str());
+
+        guards.push(literal.implies(constraint));
+        literals.push((tag.clone(), literal));
+    }
+
+    Tracked { guards, literals }
+}
+
+/// Returns `None` if a schedule exists, and a minimal unsat core over the
+/// commitment, allof, matching, ceiling and budget constraints otherwise
+pub fn explain_unsat(
+    commits: &[Commit],
+    market: &Market,
+) -> Result<Option<UnsatCore>, ScheduleError> {
+    let cfg = Config::new();
+    let ctx = Context::new(&cfg);
+    let mut encoding = encode(&ctx, commits, market)?;
+    let tracked = track(&ctx, &mut encoding);
+
+    Ok(minimal_core(&ctx, &tracked, &[]))
+}
+
+/// Deletion based core minimization: starting from all literals, drop every
+/// literal whose constraint is not needed for unsatisfiability. `fixed` is
+/// asserted in every check but never reported.
+///
+/// The guards are asserted once on a single solver, and each check only
+/// asserts the literals it assumes inside a push/pop scope.
+pub fn minimal_core<'ctx>(
+    ctx: &'ctx Context,
+    tracked: &Tracked<'ctx>,
+    fixed: &[&Ast<'ctx>],
+) -> Option<UnsatCore> {
+    let solver = Solver::new(ctx);
+    for guard in &tracked.guards {
+        solver.assert(guard);
+    }
+    for ast in fixed {
+        solver.assert(ast);
+    }
+
+    let mut core: Vec<usize> = (0..tracked.literals.len()).collect();
+    if holds_together(&solver, tracked, &core) {
+        return None;
+    }
+
+    let mut i = 0;
+    while i < core.len() {
+        let mut candidate = core.clone();
+        candidate.remove(i);
+
+        if holds_together(&solver, tracked, &candidate) {
+            // literal i is needed
+            i += 1;
+        } else {
+            core = candidate;
+        }
+    }
+
+    Some(UnsatCore {
+        constraints: core
+            .into_iter()
+            .map(|k| tracked.literals[k].0.clone())
+            .collect(),
+    })
+}
+
+/// Whether the guarded constraints of the `assumed` literals hold together
+fn holds_together<'ctx>(solver: &Solver<'ctx>, tracked: &Tracked<'ctx>, assumed: &[usize]) -> bool {
+    solver.push();
+    for k in assumed {
+        solver.assert(&tracked.literals[*k].1);
+    }
+    let sat = solver.check();
+    solver.pop(1);
+
+    sat
+}
+
+/// Why a commitment is not part of the optimal schedule
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Exclusion {
+    /// no schedule at all contains the commitment
+    Infeasible { core: UnsatCore },
+
+    /// the commitment only fits if the optimum gives up `dropped`. `schedule`
+    /// is the best schedule containing it, and keeps as much of the optimum
+    /// as possible.
+    Displaces {
+        dropped: Vec<CommitId>,
+        schedule: Schedule,
+    },
+
+    /// an equally good schedule containing the commitment exists, it lost a
+    /// tie
+    Tie { schedule: Schedule },
+
+    /// the commitment is not excluded, `schedule` is an optimum containing it
+    Scheduled { schedule: Schedule },
+}
+
+impl fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Exclusion::Infeasible { ref core } => write!(f, "{}", core),
+            Exclusion::Displaces { ref dropped, .. } => {
+                write!(f, "scheduling it means dropping")?;
+                for commit in dropped {
+                    write!(f, " {}", commit.0)?;
+                }
+                writeln!(f)
+            }
+            Exclusion::Tie { .. } => writeln!(f, "it lost a tie against an equally good schedule"),
+            Exclusion::Scheduled { .. } => writeln!(f, "it is part of the optimal schedule"),
+        }
+    }
+}
+
+/// Forces `commit_id` into the schedule and reports what stands in its way:
+/// either the constraints it can never satisfy or the commitments of the
+/// optimal schedule it would push out.
+///
+/// The optimum is the one `build_schedule_with` finds under `options`,
+/// tie-breaking included; a commitment that only loses on the tie-breaking
+/// objectives lost a tie. The limits of `options` bound each of the two
+/// optimizations, and a limit that interrupts either is an error. The unsat
+/// core is searched without limits.
+pub fn explain_exclusion(
+    commits: &[Commit],
+    market: &Market,
+    options: &ScheduleOptions,
+    commit_id: CommitId,
+) -> Result<Exclusion, ScheduleError> {
+    if commit_id.0 >= commits.len() {
+        return Err(ScheduleError::InvalidInput(format!(
+            "{} does not exist",
+            commit_id
+        )));
+    }
+
+    {
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let mut encoding = encode(&ctx, commits, market)?;
+        let forced = encoding.commit_bools[&commit_id].clone();
+
+        let tracked = track(&ctx, &mut encoding);
+        if let Some(core) = minimal_core(&ctx, &tracked, &[&forced]) {
+            return Ok(Exclusion::Infeasible { core });
+        }
+    }
+
+    let cfg = options.config();
+    let ctx = Context::new(&cfg);
+    let mut encoding = encode(&ctx, commits, market)?;
+    encoding.objectives = encoding.objectives_for(options.objective);
+    let n_primary = encoding.objectives.len();
+    let tie_break = encoding.tie_break_objective(options.tie_break, market);
+
+    // the optimum, as build_schedule_with would find it
+    encoding.objectives.extend(tie_break.iter().cloned());
+    let solver = build_optimizer(&ctx, &encoding);
+    let optimum = optimized(run_optimizer(&solver, &encoding, options)?, "no optimum")?;
+    if optimum.commits.contains(&commit_id) {
+        return Ok(Exclusion::Scheduled { schedule: optimum });
+    }
+
+    // the best schedule with the commitment forced in, keeping as many of the
+    // optimum's commitments as possible, then breaking ties as before
+    encoding.objectives.truncate(n_primary);
+    let solver = build_optimizer(&ctx, &encoding);
+    let forced = &encoding.commit_bools[&commit_id];
+    solver.assert(forced);
+
+    let mut n_kept = ctx.from_i64(0);
+    for kept in &optimum.commits {
+        let kept_bool = &encoding.commit_bools[kept];
+        n_kept = n_kept.add(&[&kept_bool.ite(&ctx.from_i64(1), &ctx.from_i64(0))]);
+    }
+    solver.maximize(&n_kept);
+    for objective in &tie_break {
+        solver.maximize(objective);
+    }
+
+    let schedule = optimized(run_optimizer(&solver, &encoding, options)?, "no schedule")?;
+
+    if schedule.objective[..] == optimum.objective[..n_primary] {
+        return Ok(Exclusion::Tie { schedule });
+    }
+
+    let dropped = optimum
+        .commits
+        .iter()
+        .filter(|commit| !schedule.commits.contains(commit))
+        .cloned()
+        .collect();
+
+    Ok(Exclusion::Displaces { dropped, schedule })
+}
+
+/// `schedule` if the optimizer proved it optimal. The commitment is known to
+/// be feasible, so anything else means a limit cut the optimization short.
+fn optimized(schedule: Schedule, found: &str) -> Result<Schedule, ScheduleError> {
+    match schedule.status {
+        Status::Sat => Ok(schedule),
+        Status::Unsat => Err(ScheduleError::SolverUnknown(format!(
+            "{} found although the commitment is feasible",
+            found
+        ))),
+        Status::Unknown { reason } => {
+            let limit = match reason {
+                UnknownReason::Timeout => "timeout",
+                UnknownReason::ResourceLimit => "resource limit",
+            };
+            Err(ScheduleError::SolverUnknown(format!(
+                "{} found before the {} ran out",
+                found, limit
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use options::Objective;
+    use tests::{allof, commit};
+
+    #[test]
+    fn unsat_core_names_the_request_nobody_offers() {
+        let commits = vec![
+            commit(vec![allof(&[("abcde-0", true), ("abcde-1", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", false)], -50)]),
+        ];
+
+        let core = explain_unsat(&commits, &Market::default())
+            .unwrap()
+            .unwrap();
+        assert!(core.constraints.iter().any(|constraint| match *constraint {
+            Constraint::NoOffer(ref request) => request.call.to_string() == "abcde-1",
+            _ => false,
+        }));
+
+        let served = vec![
+            commits[0].clone(),
+            commits[1].clone(),
+            commit(vec![allof(&[("abcde-1", false)], -50)]),
+        ];
+        assert!(explain_unsat(&served, &Market::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn exclusion_is_explained_against_the_chosen_objective() {
+        // a buyer and two sellers, the second asking for much more
+        let commits = vec![
+            commit(vec![allof(&[("abcde-0", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", false)], -10)]),
+            commit(vec![allof(&[("abcde-0", false)], -60)]),
+        ];
+        let market = Market::default();
+
+        // either seller schedules two commitments
+        let exclusion =
+            explain_exclusion(&commits, &market, &ScheduleOptions::default(), CommitId(2));
+        match exclusion.unwrap() {
+            Exclusion::Tie { schedule } | Exclusion::Scheduled { schedule } => {
+                assert!(schedule.commits.contains(&CommitId(2)))
+            }
+            other => panic!("expected a tie, got {:?}", other),
+        }
+
+        // but the first leaves the larger surplus
+        let surplus = ScheduleOptions {
+            objective: Objective::Surplus,
+            ..Default::default()
+        };
+        match explain_exclusion(&commits, &market, &surplus, CommitId(2)).unwrap() {
+            Exclusion::Displaces { dropped, schedule } => {
+                assert_eq!(dropped, vec![CommitId(1)]);
+                assert_eq!(schedule.commits, vec![CommitId(0), CommitId(2)]);
+            }
+            other => panic!("expected commit 1 to be displaced, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Index of a commitment in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CommitId(pub usize);
+
+/// Index of an allof within its commitment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct AllOfId {
+    pub commit: CommitId,
+    pub allof: usize,
+}
+
+/// A service regid together with the instance being requested or offered.
+///
+/// The regid is kept verbatim, so it may contain dashes, slashes etc.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct ServiceCall {
+    pub regid: String,
+    pub instance: String,
+}
+
+/// A service call as it appears in one particular allof
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct ServiceCallId {
+    pub allof: AllOfId,
+    pub call: ServiceCall,
+}
+
+/// A hard constraint of the encoding, identified by the rule it encodes and
+/// the ids it is about
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(tag = "rule", content = "on", rename_all = "snake_case")]
+pub enum Constraint {
+    /// a scheduled commitment schedules exactly one of its allofs
+    CommitOneOf(CommitId),
+    /// an unscheduled commitment schedules none of its allofs
+    CommitNoneOf(CommitId),
+    /// a scheduled allof schedules all of its required service calls
+    AllOfAll(AllOfId),
+    /// a scheduled allof schedules exactly k service calls of the group
+    KOf(AllOfId, usize),
+    /// an unscheduled allof schedules none of its service calls
+    AllOfNone(AllOfId),
+    /// a request nobody offers can not be scheduled
+    NoOffer(ServiceCallId),
+    /// a scheduled request is served by exactly one offer
+    OneOffer(ServiceCall),
+    /// an offer nobody requests can not be scheduled
+    NoRequest(ServiceCallId),
+    /// a scheduled offer is taken by at least one request
+    SomeRequest(ServiceCall),
+    /// at least this many commitments are scheduled
+    MinCommits(u32),
+    /// the net cost of a scheduled allof is within its ceiling
+    CostCeiling(AllOfId),
+    /// a withdrawn commitment can not be scheduled
+    Live(CommitId),
+    /// the net cost over a party's scheduled allofs is within its budget
+    Budget(String),
+    /// the net revenue over a party's scheduled allofs reaches its minimum
+    MinRevenue(String),
+    /// a scheduled request is paired with exactly one offer, an unscheduled
+    /// one with none
+    PairedRequest(ServiceCallId),
+    /// an offer is scheduled exactly when it serves some request
+    PairedOffer(ServiceCallId),
+    /// an offer serves at most its capacity of requests
+    Capacity(ServiceCallId),
+    /// the price of a service regid is at least its floor
+    PriceFloor(String),
+    /// the price of a service regid is at most its cap
+    PriceCap(String),
+    /// the price of a service regid is a whole number of ticks
+    PriceTick(String),
+    /// the price of a service regid is one of its levels
+    PriceLevel(String),
+    /// the link at this index of the market holds between its commitments
+    Link(usize),
+    /// a scheduled wildcard request is served by exactly one offered instance
+    AnyInstance(ServiceCallId),
+    /// the scheduled units requested of a service match the units offered, as
+    /// far as the market rules require
+    Balance(String),
+    /// a service call is paid for once per match it is part of
+    Payment(ServiceCallId),
+    /// an allof trades at most the units of a service it requests or offers,
+    /// and none unless scheduled
+    Traded(AllOfId, String),
+}
+
+/// What a Z3 constant created by the encoding stands for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VarId {
+    Commit(CommitId),
+    AllOf(AllOfId),
+    ServiceCall(ServiceCallId),
+    /// (request, offer) served by it
+    Match(ServiceCallId, ServiceCallId),
+    /// wildcard request, resolved to the instance in the id, is served by it
+    Wildcard(ServiceCallId),
+    /// price of a service regid
+    Price(String),
+    /// what an allof pays for a service call, negative if received
+    Payment(ServiceCallId),
+    /// a bit of the units of a service an allof trades
+    Units(AllOfId, String, u32),
+    /// assumption literal guarding a hard constraint
+    Assumption(Constraint),
+}
+
+/// Hands out Z3 constant names and maps them back to typed ids.
+///
+/// Names are short counters (`c0`, `a1`, `s2`, `m3`, `w4`, `p5`, `y6`, `u7`,
+/// `k8`) so that nothing in the user supplied regids can make two constants
+/// collide.
+#[derive(Debug, Default)]
+pub struct VarTable {
+    ids: HashMap<String, VarId>,
+}
+
+impl ServiceCall {
+    /// Instance of a request that accepts any offered instance of its regid
+    pub const WILDCARD: &'static str = "*";
+
+    pub fn new<R: Into<String>, I: Into<String>>(regid: R, instance: I) -> ServiceCall {
+        ServiceCall {
+            regid: regid.into(),
+            instance: instance.into(),
+        }
+    }
+
+    /// A request for any instance of `regid`
+    pub fn wildcard<R: Into<String>>(regid: R) -> ServiceCall {
+        ServiceCall::new(regid, ServiceCall::WILDCARD)
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.instance == ServiceCall::WILDCARD
+    }
+}
+
+impl fmt::Display for ServiceCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.regid, self.instance)
+    }
+}
+
+/// Parses the legacy `regid-instance` form. The instance is whatever follows the
+/// last dash.
+impl FromStr for ServiceCall {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ServiceCall, String> {
+        match s.rfind('-') {
+            Some(pos) if pos > 0 && pos + 1 < s.len() => {
+                Ok(ServiceCall::new(&s[..pos], &s[pos + 1..]))
+            }
+            _ => Err(format!(
+                "service call {} is not of the form regid-instance",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CommitId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "commit {}", self.0)
+    }
+}
+
+impl fmt::Display for AllOfId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "allof {} of commit {}", self.allof, self.commit.0)
+    }
+}
+
+impl fmt::Display for ServiceCallId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", self.call, self.allof)
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Constraint::CommitOneOf(commit) => {
+                write!(f, "{} must schedule exactly one of its allofs", commit)
+            }
+            Constraint::CommitNoneOf(commit) => {
+                write!(f, "{} schedules no allof unless it is scheduled", commit)
+            }
+            Constraint::AllOfAll(allof) => {
+                write!(f, "{} needs every one of its service calls", allof)
+            }
+            Constraint::KOf(allof, group) => {
+                write!(
+                    f,
+                    "{} needs k of the service calls in group {}",
+                    allof, group
+                )
+            }
+            Constraint::AllOfNone(allof) => {
+                write!(
+                    f,
+                    "{} schedules no service call unless it is scheduled",
+                    allof
+                )
+            }
+            Constraint::NoOffer(ref request) => write!(f, "request {} has no offer", request),
+            Constraint::OneOffer(ref s_call) => {
+                write!(f, "request {} must be served by exactly one offer", s_call)
+            }
+            Constraint::NoRequest(ref offer) => write!(f, "offer {} has no request", offer),
+            Constraint::SomeRequest(ref s_call) => {
+                write!(f, "offer {} must be taken by at least one request", s_call)
+            }
+            Constraint::MinCommits(n) => write!(f, "at least {} commitments must be scheduled", n),
+            Constraint::CostCeiling(allof) => {
+                write!(f, "net cost of {} must stay within its cost ceiling", allof)
+            }
+            Constraint::Live(commit) => write!(f, "{} can not be scheduled once withdrawn", commit),
+            Constraint::Budget(ref party) => {
+                write!(f, "party {} must stay within its budget", party)
+            }
+            Constraint::MinRevenue(ref party) => {
+                write!(f, "party {} must earn at least its minimum revenue", party)
+            }
+            Constraint::PairedRequest(ref request) => {
+                write!(
+                    f,
+                    "request {} must be paired with exactly one offer",
+                    request
+                )
+            }
+            Constraint::PairedOffer(ref offer) => {
+                write!(
+                    f,
+                    "offer {} is scheduled exactly when it serves a request",
+                    offer
+                )
+            }
+            Constraint::Balance(ref service) => {
+                write!(
+                    f,
+                    "units of {} requested and offered must balance as the rules require",
+                    service
+                )
+            }
+            Constraint::PriceFloor(ref regid) => {
+                write!(f, "price of {} must not go below its floor", regid)
+            }
+            Constraint::PriceCap(ref regid) => {
+                write!(f, "price of {} must not exceed its cap", regid)
+            }
+            Constraint::PriceTick(ref regid) => {
+                write!(f, "price of {} must be a multiple of the tick", regid)
+            }
+            Constraint::PriceLevel(ref regid) => {
+                write!(f, "price of {} must be one of its levels", regid)
+            }
+            Constraint::Link(index) => write!(f, "link {} between commitments must hold", index),
+            Constraint::AnyInstance(ref request) => write!(
+                f,
+                "request {} must be served by exactly one offered instance",
+                request
+            ),
+            Constraint::Capacity(ref offer) => {
+                write!(
+                    f,
+                    "offer {} can not serve more requests than its capacity",
+                    offer
+                )
+            }
+            Constraint::Payment(ref s_call) => {
+                write!(f, "{} is paid the price once per match", s_call)
+            }
+            Constraint::Traded(allof, ref service) => {
+                write!(
+                    f,
+                    "{} trades no more units of {} than it asks for",
+                    allof, service
+                )
+            }
+        }
+    }
+}
+
+impl VarTable {
+    pub fn new() -> VarTable {
+        VarTable::default()
+    }
+
+    /// Allocates a fresh constant name for `id`
+    pub fn name(&mut self, id: VarId) -> String {
+        let prefix = match id {
+            VarId::Commit(_) => "c",
+            VarId::AllOf(_) => "a",
+            VarId::ServiceCall(_) => "s",
+            VarId::Match(_, _) => "m",
+            VarId::Wildcard(_) => "w",
+            VarId::Price(_) => "p",
+            VarId::Payment(_) => "y",
+            VarId::Units(..) => "u",
+            VarId::Assumption(_) => "k",
+        };
+        let name = format!("{}{}", prefix, self.ids.len());
+        self.ids.insert(name.clone(), id);
+
+        name
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&VarId> {
+        self.ids.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_calls_split_at_the_last_dash() {
+        assert_eq!("abcde-0".parse(), Ok(ServiceCall::new("abcde", "0")));
+        assert_eq!(
+            "storage-eu-7".parse(),
+            Ok(ServiceCall::new("storage-eu", "7"))
+        );
+        assert!("abcde-*".parse::<ServiceCall>().unwrap().is_wildcard());
+        assert!("abcde".parse::<ServiceCall>().is_err());
+        assert!("-0".parse::<ServiceCall>().is_err());
+        assert!("abcde-".parse::<ServiceCall>().is_err());
+    }
+}
@@ -0,0 +1,489 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reading commitments from json.
+//!
+//! The current document shape (version 1) is
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "commits": [
+//!     { "allofs": [ { "s_calls": [["abcde-0", true]], "cost_ceil": 10 } ] }
+//!   ],
+//!   "parties": { "alice": { "budget": 100 } },
+//!   "links": [ { "kind": "requires", "commit": 0, "on": 1 } ]
+//! }
+//! ```
+//!
+//! Amounts (`cost_ceil`, budgets, price bounds) are `Money`: decimal strings
+//! such as `"12.5"` or integer numbers of whole units.
+//!
+//! Commitments may name their submitting party; the optional `parties` table
+//! gives each party a `budget` shared by all its commitments, optionally a
+//! `min_revenue`, and the `rounds_waiting` that round-robin tie-breaking
+//! favors, which `json_sched --record-round` counts on for the next round.
+//! The optional `links` constrain commitments by index: `requires`
+//! (`commit` only with `on`), `excludes` (at most one of `commits`) and
+//! `all_or_none` (all of `commits` or none). The optional `rules` object
+//! relaxes the matching policy: `min_commits` (default 1),
+//! `allow_unsold_offers`, `allow_unserved_requests` and
+//! `allow_negative_prices` (default false).
+//!
+//! The optional `prices` table bounds the price of a service regid,
+//! `{"abcde": {"min": 5, "max": 50}}`; `reserve` is accepted for `min`. Prices
+//! without a `min` are non-negative unless `allow_negative_prices` is set.
+//! The `pricing` rule picks the numbers prices are drawn from: `"integer"`
+//! whole units (the default), `"rational"` for exact fractions, reported as
+//! e.g. `"1/3"`, or `{"tick": "0.05"}` for whole multiples of a tick. A
+//! `prices` entry may set its own `tick`, and `levels`, the finite set of
+//! prices the regid may be traded at, such as the bins of
+//! `sched_benchmarks/rounding.py`.
+//!
+//! where each `s_calls` entry is a `[service_call, is_request]` pair and the
+//! service call is either a `"regid-instance"` string (the instance is whatever
+//! follows the last dash) or a `{"regid": ..., "instance": ...}` object.
+//! A request for instance `*`, or an object without an instance, accepts any
+//! offered instance of the regid. Its allof may not name any instance of that
+//! regid as well.
+//!
+//! An allof may also list `capacities`, `[service_call, capacity]` pairs for
+//! the service calls it offers to several requests at once, and `quantities`,
+//! `{"service": regid, "quantity": n, "side": "request" | "offer"}` objects
+//! asking for or offering `n` units of a service without naming instances.
+//! An allof may consist of quantities only. Finally `groups`,
+//! `{"k": k, "s_calls": [...]}` objects, list substitutable service calls of
+//! which exactly `k` (1 if omitted) are scheduled with the allof.
+//!
+//! The legacy shape written by `sched_benchmarks/generate.py`, a bare array of
+//! commits each of which is a bare array of allofs, is accepted as well.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{SerializeSeq, Serializer};
+use serde_json::{self, Value};
+use serde_path_to_error;
+
+use error::ScheduleError;
+use ids::{CommitId, ServiceCall};
+use {AllOf, Commit, Link, Market, MarketRules, Party, PriceBounds};
+
+/// Version of the commitment document this crate reads and writes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A versioned document: the commitments and the fields of their `Market`.
+/// The market fields are spelled out rather than flattened so that parse
+/// errors inside them keep their path.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub commits: Vec<Commit>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parties: BTreeMap<String, Party>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Link>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prices: BTreeMap<String, PriceBounds>,
+
+    #[serde(default, skip_serializing_if = "MarketRules::is_default")]
+    pub rules: MarketRules,
+}
+
+impl Document {
+    pub fn new(commits: Vec<Commit>) -> Document {
+        Document::with_market(commits, Market::default())
+    }
+
+    pub fn with_market(commits: Vec<Commit>, market: Market) -> Document {
+        Document {
+            version: SCHEMA_VERSION,
+            commits,
+            parties: market.parties,
+            links: market.links,
+            prices: market.prices,
+            rules: market.rules,
+        }
+    }
+
+    /// The commitments and the market they are scheduled in
+    pub fn into_parts(self) -> (Vec<Commit>, Market) {
+        let market = Market {
+            parties: self.parties,
+            links: self.links,
+            prices: self.prices,
+            rules: self.rules,
+        };
+
+        (self.commits, market)
+    }
+
+    /// Keeps the first `n` commitments. A dropped commitment is never
+    /// scheduled, so links are cut down to what that still leaves of them:
+    /// an exclusion keeps its remaining members and a requirement of a
+    /// dropped commitment goes. A link that would keep a remaining
+    /// commitment out, because it requires or is all-or-none with a dropped
+    /// one, is an error instead.
+    pub fn truncate(&mut self, n: usize) -> Result<(), ScheduleError> {
+        if n >= self.commits.len() {
+            return Ok(());
+        }
+        self.commits.truncate(n);
+
+        let kept = |commit: &CommitId| commit.0 < n;
+        let mut links = Vec::new();
+        for (i, link) in self.links.drain(..).enumerate() {
+            let cut_off = |commit: &CommitId| {
+                ScheduleError::InvalidInput(format!(
+                    "links.{}: names {}, which the first {} commits leave out",
+                    i, commit, n
+                ))
+            };
+
+            match link {
+                Link::Requires { commit, on } => {
+                    if !kept(&commit) {
+                        continue;
+                    }
+                    if !kept(&on) {
+                        return Err(cut_off(&on));
+                    }
+                    links.push(Link::Requires { commit, on });
+                }
+                Link::Excludes { commits } => {
+                    let commits: Vec<CommitId> = commits.into_iter().filter(kept).collect();
+                    if commits.len() > 1 {
+                        links.push(Link::Excludes { commits });
+                    }
+                }
+                Link::AllOrNone { commits } => {
+                    if let Some(dropped) = commits.iter().find(|commit| !kept(commit)) {
+                        if commits.iter().any(kept) {
+                            return Err(cut_off(dropped));
+                        }
+                        continue;
+                    }
+                    links.push(Link::AllOrNone { commits });
+                }
+            }
+        }
+        self.links = links;
+
+        Ok(())
+    }
+}
+
+/// Reads a commitment document in either the versioned or the legacy shape
+pub fn read_document<R: Read>(reader: R) -> Result<Document, ScheduleError> {
+    let value: Value = serde_json::from_reader(reader)?;
+
+    document_from_value(value)
+}
+
+pub fn document_from_value(value: Value) -> Result<Document, ScheduleError> {
+    match value {
+        Value::Array(_) => {
+            let commits: Vec<Vec<AllOf>> = from_value(value)?;
+
+            Ok(Document::new(
+                commits
+                    .into_iter()
+                    .map(|allofs| Commit {
+                        allofs,
+                        ..Default::default()
+                    })
+                    .collect(),
+            ))
+        }
+        Value::Object(_) => {
+            let version = value.get("version").and_then(|v| v.as_u64());
+            if version != Some(SCHEMA_VERSION as u64) {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "version: expected {}, found {}",
+                    SCHEMA_VERSION,
+                    value.get("version").unwrap_or(&Value::Null)
+                )));
+            }
+
+            from_value(value)
+        }
+        _ => Err(ScheduleError::InvalidInput(
+            "expected a commitment document or an array of commits".into(),
+        )),
+    }
+}
+
+fn from_value<'de, T: Deserialize<'de>>(value: Value) -> Result<T, ScheduleError> {
+    serde_path_to_error::deserialize(value).map_err(|err| {
+        let path = err.path().to_string();
+        ScheduleError::Parse {
+            path: Some(path),
+            err: err.into_inner(),
+        }
+    })
+}
+
+/// (De)serializes `AllOf::service_instances` as a list of
+/// `[service_call, is_request]` pairs. Service calls are written as
+/// `{"regid": ..., "instance": ...}` objects, since the `"regid-instance"`
+/// string is ambiguous for regids or instances containing a dash.
+pub mod s_calls {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        service_instances: &HashMap<ServiceCall, bool>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<(&ServiceCall, &bool)> = service_instances.iter().collect();
+        entries.sort();
+
+        let mut seq = serializer.serialize_seq(Some(entries.len()))?;
+        for (s_call, is_request) in entries {
+            seq.serialize_element(&(SCallOut::from(s_call), is_request))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<ServiceCall, bool>, D::Error> {
+        let entries: Vec<(SCallRepr, bool)> = Deserialize::deserialize(deserializer)?;
+
+        let mut service_instances = HashMap::new();
+        for (repr, is_request) in entries {
+            let s_call = repr.into_service_call().map_err(de::Error::custom)?;
+            service_instances.insert(s_call, is_request);
+        }
+
+        Ok(service_instances)
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub(super) enum SCallRepr {
+        Legacy(String),
+        Typed {
+            regid: String,
+            instance: Option<String>,
+        },
+    }
+
+    /// The object form of a service call, without an instance for a wildcard
+    #[derive(Serialize)]
+    pub(super) struct SCallOut<'a> {
+        regid: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        instance: Option<&'a str>,
+    }
+
+    impl<'a> From<&'a ServiceCall> for SCallOut<'a> {
+        fn from(s_call: &'a ServiceCall) -> SCallOut<'a> {
+            SCallOut {
+                regid: &s_call.regid,
+                instance: if s_call.is_wildcard() {
+                    None
+                } else {
+                    Some(&s_call.instance)
+                },
+            }
+        }
+    }
+
+    impl SCallRepr {
+        pub(in input) fn into_service_call(self) -> Result<ServiceCall, String> {
+            match self {
+                SCallRepr::Legacy(s) => s.parse(),
+                SCallRepr::Typed {
+                    regid,
+                    instance: Some(instance),
+                } => Ok(ServiceCall::new(regid, instance)),
+                SCallRepr::Typed {
+                    regid,
+                    instance: None,
+                } => Ok(ServiceCall::wildcard(regid)),
+            }
+        }
+    }
+}
+
+/// (De)serializes `AllOf::capacities` as a list of
+/// `[service_call, capacity]` pairs, service calls written as in `s_calls`
+pub mod capacities {
+    use super::s_calls::{SCallOut, SCallRepr};
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        capacities: &HashMap<ServiceCall, u32>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<(&ServiceCall, &u32)> = capacities.iter().collect();
+        entries.sort();
+
+        let mut seq = serializer.serialize_seq(Some(entries.len()))?;
+        for (s_call, capacity) in entries {
+            seq.serialize_element(&(SCallOut::from(s_call), capacity))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<ServiceCall, u32>, D::Error> {
+        let entries: Vec<(SCallRepr, u32)> = Deserialize::deserialize(deserializer)?;
+
+        let mut capacities = HashMap::new();
+        for (repr, capacity) in entries {
+            let s_call = repr.into_service_call().map_err(de::Error::custom)?;
+            capacities.insert(s_call, capacity);
+        }
+
+        Ok(capacities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use money::Money;
+    use serde_json::json;
+
+    #[test]
+    fn dashed_service_calls_round_trip() {
+        let mut allof = AllOf::default();
+        allof
+            .service_instances
+            .insert(ServiceCall::new("storage/eu-west", "node-7"), true);
+        allof
+            .service_instances
+            .insert(ServiceCall::wildcard("cpu-x86"), true);
+        allof
+            .service_instances
+            .insert(ServiceCall::new("gpu-a", "rack-1"), false);
+        allof
+            .capacities
+            .insert(ServiceCall::new("gpu-a", "rack-1"), 2);
+        let document = Document::new(vec![Commit {
+            allofs: vec![allof.clone()],
+            ..Default::default()
+        }]);
+
+        let value = serde_json::to_value(&document).unwrap();
+        let read = document_from_value(value).unwrap();
+
+        let read_allof = &read.commits[0].allofs[0];
+        assert_eq!(read_allof.service_instances, allof.service_instances);
+        assert_eq!(read_allof.capacities, allof.capacities);
+    }
+
+    #[test]
+    fn market_errors_keep_their_path() {
+        let value = json!({
+            "version": 1,
+            "commits": [],
+            "parties": { "a": { "budget": "ten" } }
+        });
+
+        match document_from_value(value) {
+            Err(ScheduleError::Parse { path, .. }) => {
+                assert_eq!(path, Some("parties.a.budget".to_string()))
+            }
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn legacy_arrays_and_versioned_documents_read_alike() {
+        let allof = json!({ "s_calls": [["abcde-0", true]], "cost_ceil": "2.5" });
+
+        let legacy = document_from_value(json!([[allof.clone()]])).unwrap();
+        let versioned = document_from_value(json!({
+            "version": SCHEMA_VERSION,
+            "commits": [{ "allofs": [allof], "party": "a" }],
+            "parties": { "a": { "budget": 10 } }
+        }))
+        .unwrap();
+
+        for document in &[&legacy, &versioned] {
+            let allof = &document.commits[0].allofs[0];
+            assert_eq!(allof.cost_ceil, Money::from_minor(2_500_000));
+            assert_eq!(
+                allof.service_instances.get(&ServiceCall::new("abcde", "0")),
+                Some(&true)
+            );
+        }
+        assert!(legacy.parties.is_empty());
+        assert_eq!(versioned.parties["a"].budget, Money::from(10));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        for version in &[json!(2), json!(null)] {
+            let value = json!({ "version": version, "commits": [] });
+            match document_from_value(value) {
+                Err(ScheduleError::InvalidInput(message)) => {
+                    assert!(message.starts_with("version:"), "{}", message)
+                }
+                other => panic!("expected a version error, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn truncating_cuts_links_down_to_the_kept_commitments() {
+        let mut document = Document::new(vec![Commit::default(); 4]);
+        document.links = vec![
+            Link::Requires {
+                commit: CommitId(3),
+                on: CommitId(0),
+            },
+            Link::Excludes {
+                commits: vec![CommitId(0), CommitId(1), CommitId(3)],
+            },
+            Link::Excludes {
+                commits: vec![CommitId(1), CommitId(2)],
+            },
+            Link::AllOrNone {
+                commits: vec![CommitId(2), CommitId(3)],
+            },
+        ];
+
+        let mut first_two = Document::new(vec![Commit::default(); 4]);
+        first_two.links = document.links.clone();
+        first_two.truncate(2).unwrap();
+        assert_eq!(first_two.commits.len(), 2);
+        assert_eq!(
+            first_two.links,
+            vec![Link::Excludes {
+                commits: vec![CommitId(0), CommitId(1)]
+            },]
+        );
+
+        // commit 2 would be forced out by the all-or-none group with commit 3
+        match document.truncate(3) {
+            Err(ScheduleError::InvalidInput(message)) => {
+                assert!(message.starts_with("links.3:"), "{}", message)
+            }
+            other => panic!("expected a link error, got {:?}", other),
+        }
+    }
+}
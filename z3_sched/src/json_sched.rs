@@ -20,68 +20,256 @@
 extern crate serde_json;
 extern crate z3_sched;
 
-use z3_sched::{build_schedule, AllOf, Commit};
+use z3_sched::alternatives::enumerate_schedules;
+use z3_sched::explain::{explain_exclusion, explain_unsat};
+use z3_sched::input::{read_document, Document};
+use z3_sched::settlement::settle;
+use z3_sched::{
+    build_schedule_with, Commit, CommitId, Fraction, Market, Schedule, ScheduleError,
+    ScheduleOptions, TieBreak,
+};
 
-use serde_json::{Error, Value};
-use std::collections::HashMap;
 use std::env;
 use std::fs::File;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "Usage: /path/to/json/file.json [--commits <n>] [--explain] [--why <commit>] \
+                     [--timeout <msec>] [--rlimit <n>] \
+                     [--objective commitments|surplus|commitments-then-surplus] \
+                     [--tie-break solver|lottery|round-robin|max-min] [--seed <n>] \
+                     [--settle json|csv] [--alternatives <n> [--tolerance <n>]] \
+                     [--record-round <path>]";
+
+enum LedgerFormat {
+    Json,
+    Csv,
+}
+
+struct Options {
+    path: String,
+
+    /// schedule only the first this many commitments of the file
+    commits: Option<usize>,
+
+    /// print an unsat core instead of `unsat`
+    explain: bool,
+
+    /// explain why this commitment is not scheduled
+    why: Option<CommitId>,
+
+    /// print the settlement of the schedule instead of the schedule
+    settle: Option<LedgerFormat>,
+
+    /// print up to this many optimal schedules instead of one
+    alternatives: Option<usize>,
+
+    /// how far below the optimum the primary objective of an alternative may
+    /// fall, in the unit of that objective
+    tolerance: u64,
+
+    /// write the document here with the parties' `rounds_waiting` updated
+    /// for the schedule, the input of the next round
+    record_round: Option<String>,
+
+    schedule: ScheduleOptions,
+}
 
 fn main() {
     let arguments: Vec<String> = env::args().collect();
 
-    if arguments.len() < 2 {
-        eprintln!("Usage: /path/to/json/file.json");
-        std::process::exit(1);
+    let options = match parse_args(&arguments[1..]) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n{}", msg, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("{}: {}", options.path, err);
+        std::process::exit(err.exit_code());
     }
+}
 
-    let path_to_system = &arguments[1];
+fn parse_args(arguments: &[String]) -> Result<Options, String> {
+    let mut args = arguments.iter();
+    let path = args.next().ok_or("missing path")?.clone();
+    let mut seed = None;
+    let mut options = Options {
+        path,
+        commits: None,
+        explain: false,
+        why: None,
+        settle: None,
+        alternatives: None,
+        tolerance: 0,
+        record_round: None,
+        schedule: ScheduleOptions::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--commits" => {
+                options.commits = Some(parse_number(args.next(), "--commits")? as usize);
+            }
+            "--explain" => options.explain = true,
+            "--why" => {
+                let commit = args.next().ok_or("--why needs a commit index")?;
+                let commit = commit
+                    .parse()
+                    .map_err(|_| format!("not a commit index: {}", commit))?;
+                options.why = Some(CommitId(commit));
+            }
+            "--timeout" => {
+                let msec = parse_number(args.next(), "--timeout")?;
+                options.schedule.timeout = Some(Duration::from_millis(msec));
+            }
+            "--rlimit" => options.schedule.rlimit = Some(parse_number(args.next(), "--rlimit")?),
+            "--settle" => {
+                options.settle = match args.next().map(|format| format.as_str()) {
+                    Some("json") => Some(LedgerFormat::Json),
+                    Some("csv") => Some(LedgerFormat::Csv),
+                    _ => return Err("--settle needs json or csv".into()),
+                };
+            }
+            "--objective" => {
+                let objective = args.next().ok_or("--objective needs a name")?;
+                options.schedule.objective = objective.parse()?;
+            }
+            "--tie-break" => {
+                let tie_break = args.next().ok_or("--tie-break needs a policy")?;
+                options.schedule.tie_break = tie_break.parse()?;
+            }
+            "--seed" => seed = Some(parse_number(args.next(), "--seed")?),
+            "--alternatives" => {
+                let limit = parse_number(args.next(), "--alternatives")?;
+                options.alternatives = Some(limit as usize);
+            }
+            "--tolerance" => options.tolerance = parse_number(args.next(), "--tolerance")?,
+            "--record-round" => {
+                let path = args.next().ok_or("--record-round needs a path")?;
+                options.record_round = Some(path.clone());
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
 
-    build_allofs(path_to_system.to_string());
+    match (options.schedule.tie_break, seed) {
+        (TieBreak::Lottery { .. }, Some(seed)) => {
+            options.schedule.tie_break = TieBreak::Lottery { seed }
+        }
+        (TieBreak::Lottery { .. }, None) => {
+            // a fresh draw each run, printed so that the run can be repeated
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos() as u64)
+                .unwrap_or(0);
+            eprintln!("lottery seed {}", seed);
+            options.schedule.tie_break = TieBreak::Lottery { seed }
+        }
+        (_, Some(_)) => return Err("--seed needs --tie-break lottery".into()),
+        (_, None) => {}
+    }
+
+    let scheduling = options.why.is_none() && !options.explain && options.alternatives.is_none();
+    if options.record_round.is_some() && !scheduling {
+        return Err(
+            "--record-round needs a schedule, not --why, --explain or --alternatives".into(),
+        );
+    }
+
+    Ok(options)
 }
 
-fn build_allofs(path: String) {
-    let mut f = File::open(path).expect("File not found");
-    let v: Value = serde_json::from_reader(f).expect("Expected json payload in file");
-
-    // this is an array of allofs
-    let commits_values = v.as_array().unwrap();
-
-    let mut commits = Vec::new();
-
-    for commit_value in commits_values {
-        let mut allofs = Vec::new();
-        let allofs_values = commit_value.as_array().unwrap();
-
-        for allof_value in allofs_values {
-            let s_calls = allof_value.as_object().unwrap().get("s_calls").unwrap();
-            let mut service_instances = HashMap::new();
-            let cost_ceil = allof_value
-                .as_object()
-                .unwrap()
-                .get("cost_ceil")
-                .unwrap()
-                .as_i64()
-                .unwrap();
-            for s_call_entry in s_calls.as_array().unwrap() {
-                let s_call_arr = s_call_entry.as_array().unwrap();
-                let s_call = s_call_arr[0].as_str().unwrap().to_string();
-                let is_request = s_call_arr[1].as_bool().unwrap();
-                service_instances.insert(s_call.to_string(), is_request);
-            }
+fn parse_number(arg: Option<&String>, flag: &str) -> Result<u64, String> {
+    let arg = arg.ok_or_else(|| format!("{} needs a number", flag))?;
 
-            allofs.push(AllOf {
-                service_instances: service_instances,
-                cost_ceil: cost_ceil,
-            })
+    arg.parse()
+        .map_err(|_| format!("{} needs a number, got {}", flag, arg))
+}
+
+fn run(options: &Options) -> Result<(), ScheduleError> {
+    let document = build_allofs(options.path.clone(), options.commits)?;
+    let (commits, market) = document.into_parts();
+
+    if let Some(commit_id) = options.why {
+        let exclusion = explain_exclusion(&commits, &market, &options.schedule, commit_id)?;
+        eprint!("{}", exclusion);
+        println!("{}", serde_json::to_string_pretty(&exclusion)?);
+        return Ok(());
+    }
+
+    if options.explain {
+        if let Some(core) = explain_unsat(&commits, &market)? {
+            eprint!("{}", core);
+            println!("{}", serde_json::to_string_pretty(&core)?);
+            return Ok(());
         }
+    }
 
-        commits.push(Commit { allofs: allofs });
+    if let Some(ref format) = options.settle {
+        let schedule = build_schedule_with(commits.clone(), &market, &options.schedule)?;
+        let settlement = settle(&schedule, &commits)?;
 
-        if commits.len() > 10 {
-            break;
+        match *format {
+            LedgerFormat::Json => println!("{}", serde_json::to_string_pretty(&settlement)?),
+            LedgerFormat::Csv => print!("{}", settlement.to_csv()),
         }
+        return record_round(options, market, &schedule, &commits);
+    }
+
+    if let Some(limit) = options.alternatives {
+        let tolerance = Fraction::from_integer(options.tolerance as i64);
+        let alternatives =
+            enumerate_schedules(&commits, &market, &options.schedule, limit, tolerance)?;
+        if let Some(reason) = alternatives.interrupted {
+            eprintln!(
+                "warning: enumeration stopped early ({:?}) after {} schedules",
+                reason,
+                alternatives.schedules.len()
+            );
+        }
+        println!("{}", serde_json::to_string_pretty(&alternatives)?);
+        return Ok(());
+    }
+
+    let schedule = build_schedule_with(commits.clone(), &market, &options.schedule)?;
+    println!("{}", serde_json::to_string_pretty(&schedule)?);
+
+    record_round(options, market, &schedule, &commits)
+}
+
+/// Writes the input document to `--record-round`, if given, with the
+/// parties' `rounds_waiting` counted on by `schedule`. The rest of the
+/// document, including any commitments `--commits` left out, is copied as
+/// read.
+fn record_round(
+    options: &Options,
+    mut market: Market,
+    schedule: &Schedule,
+    commits: &[Commit],
+) -> Result<(), ScheduleError> {
+    let path = match options.record_round {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+
+    market.record_round(schedule, commits);
+    let mut document = read_document(File::open(&options.path)?)?;
+    document.parties = market.parties;
+
+    let f = File::create(path)?;
+    serde_json::to_writer_pretty(f, &document)?;
+    Ok(())
+}
+
+fn build_allofs(path: String, commits: Option<usize>) -> Result<Document, ScheduleError> {
+    let f = File::open(path)?;
+    let mut document = read_document(f)?;
+
+    if let Some(commits) = commits {
+        document.truncate(commits)?;
     }
 
-    build_schedule(commits);
+    Ok(document)
 }
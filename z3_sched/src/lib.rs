@@ -16,70 +16,420 @@
 //  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
 //  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 //  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
-
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_path_to_error;
 extern crate z3;
 
-use std::collections::HashMap;
+pub mod alternatives;
+mod error;
+pub mod explain;
+mod ids;
+pub mod input;
+mod market;
+pub mod money;
+mod options;
+mod schedule;
+mod scheduler;
+pub mod settlement;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::time::Instant;
 use z3::*;
 
-#[derive(Debug)]
+pub use error::ScheduleError;
+pub use ids::{AllOfId, CommitId, Constraint, ServiceCall, ServiceCallId, VarId, VarTable};
+pub use market::{Link, Market, MarketRules, Party, PriceBounds, Pricing};
+pub use money::{Fraction, Money, Price};
+pub use options::{Objective, ScheduleOptions, TieBreak};
+pub use schedule::{
+    Match, Schedule, ScheduledQuantity, ScheduledServiceCall, Status, UnknownReason,
+};
+pub use scheduler::Scheduler;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Commit {
     pub allofs: Vec<AllOf>,
+
+    /// value of scheduling this commitment, 1 if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i64>,
+
+    /// priority tier. Tiers are maximized lexicographically, lowest first;
+    /// commitments without a tier come after every tier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<u32>,
+
+    /// id of the submitting party in `Market::parties`, whose budget this
+    /// commitment draws on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub party: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AllOf {
-    pub service_instances: HashMap<String, bool>,
-    pub cost_ceil: i64,
+    #[serde(rename = "s_calls", default, with = "input::s_calls")]
+    pub service_instances: HashMap<ServiceCall, bool>,
+    pub cost_ceil: Money,
+
+    /// offered service call -> most requests it can serve. An instance some
+    /// offer gives a capacity for is multi-tenant: its requests are spread
+    /// over all its offers instead of being served by a single one.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        with = "input::capacities"
+    )]
+    pub capacities: HashMap<ServiceCall, u32>,
+
+    /// units of a service requested or offered without naming instances
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quantities: Vec<Quantity>,
+
+    /// groups of substitutable service calls, each scheduled `k` at a time
+    /// along with the allof
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<KOf>,
+}
+
+impl AllOf {
+    /// Every service call of the allof, required or in a group, along with
+    /// whether it is a request
+    pub fn s_calls(&self) -> Vec<(&ServiceCall, bool)> {
+        let groups = self
+            .groups
+            .iter()
+            .flat_map(|group| &group.service_instances);
+
+        self.service_instances
+            .iter()
+            .chain(groups)
+            .map(|(s_call, &is_request)| (s_call, is_request))
+            .collect()
+    }
+}
+
+/// Exactly `k` of the service calls of a group are scheduled with their allof.
+/// `k` defaults to 1, making the group an any-of over substitutes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KOf {
+    #[serde(default = "KOf::any")]
+    pub k: u32,
+
+    #[serde(rename = "s_calls", with = "input::s_calls")]
+    pub service_instances: HashMap<ServiceCall, bool>,
 }
 
-/// build vars
-pub fn build_schedule(commits: Vec<Commit>) {
-    let cfg = Config::new();
+impl KOf {
+    fn any() -> u32 {
+        1
+    }
+}
+
+/// A number of units of a service regid. Units are pooled per regid: the
+/// units requested by the scheduled allofs must add up to the units they offer,
+/// unless the market rules leave some unsold or unserved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quantity {
+    /// service regid
+    pub service: String,
+    pub quantity: u32,
+    pub side: Side,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Request,
+    Offer,
+}
+
+impl Side {
+    pub fn is_request(self) -> bool {
+        self == Side::Request
+    }
+}
+
+/// Encodes the commitments, maximizes the number of scheduled commitments and
+/// returns the resulting schedule.
+pub fn build_schedule(commits: Vec<Commit>) -> Result<Schedule, ScheduleError> {
+    build_schedule_with(commits, &Market::default(), &ScheduleOptions::default())
+}
+
+/// `build_schedule` within the party budgets and links of `market`, with the objective
+/// and solver limits in `options`
+pub fn build_schedule_with(
+    commits: Vec<Commit>,
+    market: &Market,
+    options: &ScheduleOptions,
+) -> Result<Schedule, ScheduleError> {
+    let cfg = options.config();
     let ctx = Context::new(&cfg);
+    let mut encoding = encode(&ctx, &commits, market)?;
+    encoding.objectives = encoding.objectives_for(options.objective);
+    let tie_break = encoding.tie_break_objective(options.tie_break, market);
+    encoding.objectives.extend(tie_break);
+
+    let solver = build_optimizer(&ctx, &encoding);
+    run_optimizer(&solver, &encoding, options)
+}
+
+/// Checks `solver` once, under the limits of `options` that its context was
+/// configured with.
+///
+/// The binding only tells whether a check was sat, so a failed check is told
+/// apart from a proof of unsat by what Z3 left behind. When a limit
+/// interrupts the optimization Z3 keeps the best model found so far: if it
+/// satisfies every hard constraint it is returned as `best_so_far`. Without
+/// such a model the result is `Unknown` if the timeout ran out or an rlimit is
+/// set, since a check that ends early under an rlimit may have hit it, and
+/// `Unsat` otherwise.
+pub fn run_optimizer<'ctx>(
+    solver: &Optimize<'ctx>,
+    encoding: &Encoding<'ctx>,
+    options: &ScheduleOptions,
+) -> Result<Schedule, ScheduleError> {
+    let started = Instant::now();
+    if solver.check() {
+        return read_schedule(&solver.get_model(), encoding);
+    }
+    if !options.has_limits() {
+        return Ok(Schedule::unsat());
+    }
+
+    let timed_out = options
+        .timeout
+        .is_some_and(|timeout| started.elapsed() >= timeout);
+    let reason = if timed_out || options.rlimit.is_none() {
+        UnknownReason::Timeout
+    } else {
+        UnknownReason::ResourceLimit
+    };
+
+    let model = solver.get_model();
+    if satisfies(&model, encoding) {
+        // a schedule exists, so the optimization was cut short
+        let best_so_far = read_schedule(&model, encoding)?;
+        return Ok(Schedule::unknown(reason, Some(best_so_far)));
+    }
+
+    if timed_out || options.rlimit.is_some() {
+        Ok(Schedule::unknown(reason, None))
+    } else {
+        Ok(Schedule::unsat())
+    }
+}
 
-    // contains entries of the form:
-    //  [
-    //    '0': Bool('0')
-    //  ]
-    let commit_bools = build_commit_level_vars(&ctx, &commits);
+/// Every hard constraint of `encoding` holds in `model`
+fn satisfies<'ctx>(model: &Model<'ctx>, encoding: &Encoding<'ctx>) -> bool {
+    encoding
+        .constraints
+        .iter()
+        .all(|(_, constraint)| eval_bool(model, constraint).unwrap_or(false))
+}
 
-    // contains entries of the form:
-    // [
-    //   '0-0': Bool('0-0') - commit 0, allof 0
-    // ]
-    let allof_bools = build_allof_level_bools(&ctx, &commits);
+/// The variables and hard constraints for a set of commitments.
+///
+/// Commitments are added one at a time; the constraints matching requests
+/// with offers span commitments and are built on demand for a service call.
+pub struct Encoding<'ctx> {
+    ctx: &'ctx Context,
+
+    pub names: VarTable,
+
+    pub commit_bools: HashMap<CommitId, Ast<'ctx>>,
+    pub allof_bools: HashMap<AllOfId, Ast<'ctx>>,
+    pub s_call_bools: HashMap<ServiceCallId, (Ast<'ctx>, bool)>,
+    /// service regid -> price, in minor units of `Money`
+    pub s_call_prices: HashMap<String, Ast<'ctx>>,
+    /// service call -> what its allof pays for it, the price once per match
+    /// and negated for an offer. Defined along with the matching constraints
+    /// of the service call.
+    pub s_call_payments: HashMap<ServiceCallId, Ast<'ctx>>,
+
+    /// allof -> net cost when scheduled, 0 otherwise
+    pub allof_costs: HashMap<AllOfId, Ast<'ctx>>,
+    /// allof -> `cost_ceil - net cost` when scheduled, 0 otherwise
+    pub allof_surplus: HashMap<AllOfId, Ast<'ctx>>,
+
+    /// service call -> ids of the allof entries requesting it
+    pub requests: HashMap<ServiceCall, Vec<ServiceCallId>>,
+    /// service call -> ids of the allof entries offering it
+    pub offers: HashMap<ServiceCall, Vec<ServiceCallId>>,
+
+    /// service regid -> (allof, units, units traded) of the allofs
+    /// requesting units of it
+    pub unit_requests: HashMap<String, Vec<(AllOfId, u32, Ast<'ctx>)>>,
+    /// service regid -> (allof, units, units traded) of the allofs offering
+    /// units of it
+    pub unit_offers: HashMap<String, Vec<(AllOfId, u32, Ast<'ctx>)>>,
+
+    /// (request, offer) -> the offer serves the request. Built along with
+    /// the matching constraints of the service call.
+    pub match_bools: HashMap<(ServiceCallId, ServiceCallId), Ast<'ctx>>,
+    /// wildcard request resolved to an instance -> it is served by that
+    /// instance. Built along with the matching constraints of the instance.
+    pub wildcard_bools: HashMap<ServiceCallId, Ast<'ctx>>,
+    /// offer -> most requests it can serve, for the offers that declare it
+    pub offer_capacities: HashMap<ServiceCallId, u32>,
+
+    /// policy for the matching constraints
+    pub rules: MarketRules,
+
+    /// every hard constraint, tagged with the rule it encodes. Filled in by
+    /// `encode`; the incremental `Scheduler` sets it to the constraints in
+    /// force for each solve.
+    pub constraints: Vec<(Constraint, Ast<'ctx>)>,
+
+    /// commit -> (weight, tier)
+    pub commit_values: HashMap<CommitId, (i64, Option<u32>)>,
+    /// commit -> submitting party, for the commitments that name one
+    pub commit_parties: HashMap<CommitId, String>,
+
+    /// most important first, maximized lexicographically. `encode` sets the
+    /// tiered commitment count, see `objectives_for` for the alternatives.
+    pub objectives: Vec<Ast<'ctx>>,
+}
+
+/// Builds the variables and hard constraints for `commits` in `market` without
+/// asserting them anywhere
+pub fn encode<'ctx>(
+    ctx: &'ctx Context,
+    commits: &[Commit],
+    market: &Market,
+) -> Result<Encoding<'ctx>, ScheduleError> {
+    validate_commits(commits)?;
+
+    let mut encoding = Encoding::new(ctx);
+    encoding.rules = market.rules.clone();
+    let mut constraints = Vec::new();
+
+    for (i, commit) in commits.iter().enumerate() {
+        let commit_constraints = encoding.add_commit(CommitId(i), commit)?;
+        constraints.extend(commit_constraints);
+    }
+
+    let s_calls = encoding.matching_order(encoding.requests.keys().chain(encoding.offers.keys()));
+
+    for s_call in &s_calls {
+        constraints.extend(encoding.matching_constraints(s_call));
+    }
 
-    // contains entries of the form:
-    // [
-    //   '0-0-abcde-0': Bool('0-0-abce-0') - commit 0, allof 0, service_call_regid abcde, instance 0
-    // ]
-    let s_call_bools = build_scall_level_bools(&ctx, &commits);
+    constraints.extend(encoding.balance_constraints());
+    constraints.extend(encoding.party_constraints(market)?);
+    constraints.extend(encoding.link_constraints(market)?);
+    constraints.extend(encoding.price_constraints(market)?);
+    let min_commits = market.rules.min_commits;
+    constraints.push((
+        Constraint::MinCommits(min_commits),
+        encoding.min_commits(min_commits),
+    ));
 
-    // first group service-call names by their allof
-    let s_calls_grouped = group_s_calls(&s_call_bools);
+    encoding.constraints = constraints;
+    encoding.objectives = encoding.tiered_objectives();
 
-    // grab count vars
-    let s_call_prices = build_count_vars(&ctx, &commits);
+    Ok(encoding)
+}
 
-    // group allofs by the commitment they are part of
-    let allofs_grouped = group_allofs(&allof_bools);
+impl<'ctx> Encoding<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> Encoding<'ctx> {
+        Encoding {
+            ctx,
+            names: VarTable::new(),
+            commit_bools: HashMap::new(),
+            allof_bools: HashMap::new(),
+            s_call_bools: HashMap::new(),
+            s_call_prices: HashMap::new(),
+            s_call_payments: HashMap::new(),
+            allof_costs: HashMap::new(),
+            allof_surplus: HashMap::new(),
+            requests: HashMap::new(),
+            offers: HashMap::new(),
+            unit_requests: HashMap::new(),
+            unit_offers: HashMap::new(),
+            match_bools: HashMap::new(),
+            wildcard_bools: HashMap::new(),
+            offer_capacities: HashMap::new(),
+            rules: MarketRules::default(),
+            constraints: Vec::new(),
+            commit_values: HashMap::new(),
+            commit_parties: HashMap::new(),
+            objectives: Vec::new(),
+        }
+    }
 
-    println!("{:#?}", s_call_bools.keys());
+    /// Creates the variables of `commit` and returns the constraints that only
+    /// concern this one commitment
+    pub fn add_commit(
+        &mut self,
+        commit_id: CommitId,
+        commit: &Commit,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError> {
+        let ctx = self.ctx;
+
+        if self.commit_bools.contains_key(&commit_id) {
+            return Err(ScheduleError::InvalidInput(format!(
+                "{} is already encoded",
+                commit_id
+            )));
+        }
+        validate_commit(commit_id, commit)?;
+
+        // contains entries of the form:
+        //  [
+        //    CommitId(0): Bool('c0')
+        //  ]
+        let commit_var = build_commit_level_var(ctx, commit_id, &mut self.names);
+
+        // contains entries of the form:
+        // [
+        //   AllOfId { commit 0, allof 0 }: Bool('a1')
+        // ]
+        let allof_bools = build_allof_level_bools(ctx, commit_id, commit, &mut self.names);
+
+        // contains entries of the form:
+        // [
+        //   ServiceCallId { allof 0 of commit 0, abcde-0 }: (Bool('s2'), is_request)
+        // ]
+        let s_call_bools = build_scall_level_bools(ctx, commit_id, commit, &mut self.names);
+
+        // grab count vars for services not seen before
+        build_count_vars(
+            ctx,
+            commit,
+            self.rules.pricing,
+            &mut self.names,
+            &mut self.s_call_prices,
+        );
+
+        // what each service call is paid, see `payment_constraints`
+        let mut s_call_payments = HashMap::new();
+        let mut s_call_ids: Vec<&ServiceCallId> = s_call_bools.keys().collect();
+        s_call_ids.sort();
+        for s_call_id in s_call_ids {
+            let var_name = self.names.name(VarId::Payment(s_call_id.clone()));
+            let payment = price_const(ctx, self.rules.pricing, var_name.as_str());
+            s_call_payments.insert(s_call_id.clone(), payment);
+        }
 
-    println!("{:#?}", s_calls_grouped);
-    println!("{:#?}", allofs_grouped);
+        // first group service-call ids by their allof
+        let s_calls_grouped = group_s_calls(&s_call_bools);
 
-    let solver = Optimize::new(&ctx);
+        // group allofs by the commitment they are part of
+        let allofs_grouped = group_allofs(&allof_bools);
 
-    // a commitment is scheduled implies that exactly one of its allofs is scheduled
-    for (commit_var_name, commit_var) in &commit_bools {
-        let commit_allofs = &allofs_grouped[commit_var_name];
+        let mut constraints = Vec::new();
+        let commit_allofs = &allofs_grouped[&commit_id];
 
         let mut allof_vars = Vec::new();
-        for var_name in commit_allofs {
-            allof_vars.push(&allof_bools[var_name]);
+        for allof_id in commit_allofs {
+            allof_vars.push(&allof_bools[allof_id]);
         }
+
+        // a commitment is scheduled implies that exactly one of its allofs is scheduled
         let mut allof_coeffs = vec![];
         for _ in commit_allofs {
             allof_coeffs.push(1);
@@ -87,19 +437,12 @@ pub fn build_schedule(commits: Vec<Commit>) {
         let allof_count_clause =
             allof_vars[0].pb_eq(&allof_vars[1..allof_vars.len()], allof_coeffs, 1);
 
-        solver.assert(&commit_var.implies(&allof_count_clause));
-    }
-
-    println!("commitment one_of clause built");
-
-    // if a commitment is not scheduled then none of the included allofs should be scheduled
-    for (commit_var_name, commit_var) in &commit_bools {
-        let commit_allofs = &allofs_grouped[commit_var_name];
+        constraints.push((
+            Constraint::CommitOneOf(commit_id),
+            commit_var.implies(&allof_count_clause),
+        ));
 
-        let mut allof_vars = Vec::new();
-        for var_name in commit_allofs {
-            allof_vars.push(&allof_bools[var_name]);
-        }
+        // if a commitment is not scheduled then none of the included allofs should be scheduled
         let mut allof_coeffs = vec![];
         for _ in commit_allofs {
             allof_coeffs.push(1);
@@ -107,298 +450,1325 @@ pub fn build_schedule(commits: Vec<Commit>) {
         let allof_count_clause2 =
             allof_vars[0].pb_eq(&allof_vars[1..allof_vars.len()], allof_coeffs, 0);
 
-        solver.assert(&commit_var.not().implies(&allof_count_clause2));
-    }
+        constraints.push((
+            Constraint::CommitNoneOf(commit_id),
+            commit_var.not().implies(&allof_count_clause2),
+        ));
+
+        for allof_id in commit_allofs {
+            let allof = &commit.allofs[allof_id.allof];
+            let allof_var = &allof_bools[allof_id];
+            let allof_s_calls = match s_calls_grouped.get(allof_id) {
+                Some(allof_s_calls) => allof_s_calls,
+                // only quantities, which are tied to the allof variable itself
+                None => continue,
+            };
+
+            let mut s_call_vars = Vec::new();
+            for s_call_id in allof_s_calls {
+                s_call_vars.push(&s_call_bools[s_call_id].0);
+            }
+
+            // if a particular allof is scheduled then we need to schedule all its required
+            // service calls
+            let required_vars: Vec<&Ast<'ctx>> = allof_s_calls
+                .iter()
+                .filter(|id| allof.service_instances.contains_key(&id.call))
+                .map(|id| &s_call_bools[id].0)
+                .collect();
+            if !required_vars.is_empty() {
+                let allof_scall_clause = required_vars[0].and(&required_vars[1..]);
+
+                constraints.push((
+                    Constraint::AllOfAll(*allof_id),
+                    allof_var.implies(&allof_scall_clause),
+                ));
+            }
+
+            // and exactly k of every group
+            for (g, group) in allof.groups.iter().enumerate() {
+                let mut group_ids: Vec<ServiceCallId> = group
+                    .service_instances
+                    .keys()
+                    .map(|s_call| ServiceCallId {
+                        allof: *allof_id,
+                        call: s_call.clone(),
+                    })
+                    .collect();
+                group_ids.sort();
+
+                let group_vars = self.s_call_vars_in(&s_call_bools, &group_ids);
+                let coeffs = vec![1; group_vars.len()];
+                let k_clause = group_vars[0].pb_eq(&group_vars[1..], coeffs, group.k as i32);
+
+                constraints.push((Constraint::KOf(*allof_id, g), allof_var.implies(&k_clause)));
+            }
+
+            // if an allof is not scheduled, none of the constituent s_calls must be scheduled
+            let mut coeffs = Vec::new();
+            for _ in allof_s_calls {
+                coeffs.push(1);
+            }
+
+            let allof_scall_clause2 =
+                s_call_vars[0].pb_eq(&s_call_vars[1..s_call_vars.len()], coeffs, 0);
+
+            constraints.push((
+                Constraint::AllOfNone(*allof_id),
+                allof_var.not().implies(&allof_scall_clause2),
+            ));
+        }
+
+        // make sure that the cost ceilings are respected
+        let mut allof_net_costs = HashMap::new();
+        let mut allof_surplus = HashMap::new();
+        let mut traded_units = Vec::new();
+        for (j, allof) in commit.allofs.iter().enumerate() {
+            let cost_ceil = self.amount(allof.cost_ceil.minor());
+            let allof_id = AllOfId {
+                commit: commit_id,
+                allof: j,
+            };
+            let allof_var = &allof_bools[&allof_id];
+
+            let no_ids = Vec::new();
+            let s_calls = s_calls_grouped.get(&allof_id).unwrap_or(&no_ids);
+            let mut allof_costs = self.amount(0);
+
+            // service calls are paid per match, so unmatched ones and group
+            // members not chosen cost nothing
+            for s_call_id in s_calls {
+                allof_costs = allof_costs.add(&[&s_call_payments[s_call_id]]);
+            }
 
-    println!("commitment one_of negation clause built");
+            // units are paid as traded, which the rules may leave short of
+            // what is requested or offered. The traded units are summed bit by
+            // bit so that their cost stays linear in the price.
+            for quantity in &allof.quantities {
+                let sign = if quantity.side.is_request() { 1 } else { -1 };
+
+                let cost_var = self.s_call_prices.get(&quantity.service).ok_or_else(|| {
+                    ScheduleError::Encoding(format!(
+                        "no price variable for service {}",
+                        quantity.service
+                    ))
+                })?;
+
+                let mut traded = ctx.from_i64(0);
+                let mut bit = 0;
+                while u64::from(quantity.quantity) >> bit > 0 {
+                    let var_name =
+                        self.names
+                            .name(VarId::Units(allof_id, quantity.service.clone(), bit));
+                    let bit_var = ctx.named_bool_const(var_name.as_str());
+                    let units = 1i64 << bit;
+
+                    traded = traded.add(&[&bit_var.ite(&ctx.from_i64(units), &ctx.from_i64(0))]);
+                    let cost_entry = cost_var.mul(&[&self.amount(sign * units)]);
+                    allof_costs = allof_costs.add(&[&bit_var.ite(&cost_entry, &self.amount(0))]);
+                    bit += 1;
+                }
 
-    // if a particular allof is scheduled then we need to schedule all its service
-    // calls
-    for (allofs_var_name, allof_var) in &allof_bools {
-        println!("allof var name {}", allofs_var_name);
-        let allof_s_calls = &s_calls_grouped[allofs_var_name];
+                let most = allof_var.ite(
+                    &ctx.from_i64(i64::from(quantity.quantity)),
+                    &ctx.from_i64(0),
+                );
+                constraints.push((
+                    Constraint::Traded(allof_id, quantity.service.clone()),
+                    traded.le(&most),
+                ));
+                traded_units.push((allof_id, quantity, traded));
+            }
+            // an allof that is not scheduled pays nothing and must not bound
+            // the prices shared with everyone else
+            constraints.push((
+                Constraint::CostCeiling(allof_id),
+                allof_var.implies(&allof_costs.le(&cost_ceil)),
+            ));
+
+            let surplus = allof_var.ite(&cost_ceil.sub(&[&allof_costs]), &self.amount(0));
+            allof_surplus.insert(allof_id, surplus);
+            allof_net_costs.insert(allof_id, allof_var.ite(&allof_costs, &self.amount(0)));
+        }
 
-        println!("Contains s_calls: {:?}", allof_s_calls);
+        // index the new service calls by the service instance they request or offer
+        let mut s_call_ids: Vec<&ServiceCallId> = s_call_bools.keys().collect();
+        s_call_ids.sort();
+        for s_call_id in s_call_ids {
+            let group = if s_call_bools[s_call_id].1 {
+                &mut self.requests
+            } else {
+                &mut self.offers
+            };
 
-        let mut s_call_vars = Vec::new();
-        for var_name in allof_s_calls {
-            s_call_vars.push(&s_call_bools[var_name].0);
+            let entry = group.entry(s_call_id.call.clone()).or_insert(Vec::new());
+            entry.push(s_call_id.clone());
         }
 
-        let allof_scall_clause = s_call_vars[0].and(&s_call_vars[1..s_call_vars.len()]);
+        for (allof_id, quantity, traded) in traded_units {
+            let group = if quantity.side.is_request() {
+                &mut self.unit_requests
+            } else {
+                &mut self.unit_offers
+            };
+
+            let entry = group.entry(quantity.service.clone()).or_insert(Vec::new());
+            entry.push((allof_id, quantity.quantity, traded));
+        }
 
-        solver.assert(&allof_var.implies(&allof_scall_clause));
+        for (j, allof) in commit.allofs.iter().enumerate() {
+            for (s_call, capacity) in &allof.capacities {
+                let s_call_id = ServiceCallId {
+                    allof: AllOfId {
+                        commit: commit_id,
+                        allof: j,
+                    },
+                    call: s_call.clone(),
+                };
+                self.offer_capacities.insert(s_call_id, *capacity);
+            }
+        }
+
+        self.commit_bools.insert(commit_id, commit_var);
+        self.commit_values
+            .insert(commit_id, (commit.weight.unwrap_or(1), commit.tier));
+        self.allof_bools.extend(allof_bools);
+        self.allof_costs.extend(allof_net_costs);
+        self.allof_surplus.extend(allof_surplus);
+        if let Some(ref party) = commit.party {
+            self.commit_parties.insert(commit_id, party.clone());
+        }
+        self.s_call_bools.extend(s_call_bools);
+        self.s_call_payments.extend(s_call_payments);
+
+        Ok(constraints)
     }
 
-    println!("allof service_call clause built");
+    /// Request and offer clauses for one service instance, over every
+    /// commitment added so far.
+    ///
+    /// Wildcard requests of the regid take part as requests for this instance
+    /// through a fresh literal per instance, which the clauses of the wildcard
+    /// itself tie together. Those have to be rebuilt after the instance, see
+    /// `matching_order`.
+    pub fn matching_constraints(&mut self, s_call: &ServiceCall) -> Vec<(Constraint, Ast<'ctx>)> {
+        if s_call.is_wildcard() {
+            return self.wildcard_constraints(s_call);
+        }
+
+        let mut constraints = Vec::new();
+        let no_ids = Vec::new();
+        let offer_ids = self.offers.get(s_call).unwrap_or(&no_ids).clone();
+
+        // requests naming this instance, then wildcards resolved to it
+        let mut requests: Vec<(ServiceCallId, Ast<'ctx>)> = self
+            .requests
+            .get(s_call)
+            .unwrap_or(&no_ids)
+            .iter()
+            .map(|req_id| (req_id.clone(), self.s_call_bools[req_id].0.clone()))
+            .collect();
+        if !offer_ids.is_empty() {
+            requests.extend(self.resolve_wildcards(s_call));
+        }
+
+        let req_ids: Vec<ServiceCallId> = requests.iter().map(|req| req.0.clone()).collect();
+        let req_var_refs: Vec<&Ast<'ctx>> = requests.iter().map(|req| &req.1).collect();
+        let offer_var_refs = self.s_call_vars(&offer_ids);
+
+        let multi_tenant = offer_ids
+            .iter()
+            .any(|offer_id| self.offer_capacities.contains_key(offer_id));
+
+        // if a request has been scheduled, exactly one offer must be scheduled for it.
+        // Multi-tenant instances leave that to the pairing and capacity constraints.
+        if !req_ids.is_empty() && !self.rules.allow_unserved_requests {
+            if offer_ids.is_empty() {
+                // then this service call set can basically not be scheduled
+                for (req_id, var) in req_ids.iter().zip(&req_var_refs) {
+                    constraints.push((Constraint::NoOffer(req_id.clone()), var.not()));
+                }
+            } else if !multi_tenant {
+                let request_clause = req_var_refs[0].or(&req_var_refs[1..req_var_refs.len()]);
+
+                // clause needed if multiple offers exist for this one request
+                if offer_var_refs.len() > 1 {
+                    let mut coeffs = Vec::<i32>::new();
+                    for _ in &offer_var_refs {
+                        coeffs.push(1);
+                    }
+
+                    let one_offer_clause = offer_var_refs[0].pb_eq(
+                        &offer_var_refs[1..offer_var_refs.len()],
+                        coeffs,
+                        1,
+                    );
+
+                    constraints.push((
+                        Constraint::OneOffer(s_call.clone()),
+                        request_clause.implies(&one_offer_clause),
+                    ));
+                }
+                // if only one offer exists however, use this clause
+                else {
+                    constraints.push((
+                        Constraint::OneOffer(s_call.clone()),
+                        request_clause.implies(offer_var_refs[0]),
+                    ));
+                }
+            }
+        }
+
+        // if a service call is being offered, then at least one request must exist for it
+        if !offer_ids.is_empty() && !self.rules.allow_unsold_offers {
+            if req_ids.is_empty() {
+                // do not schedule this service call
+                for (offer_id, var) in offer_ids.iter().zip(&offer_var_refs) {
+                    constraints.push((Constraint::NoRequest(offer_id.clone()), var.not()));
+                }
+            } else {
+                let mut coeffs = Vec::<i32>::new();
+
+                let offer_clause = offer_var_refs[0].or(&offer_var_refs[1..offer_var_refs.len()]);
 
-    // if an allof is not scheduled, none of the constituent s_calls must be scheduled
-    for (allofs_var_name, allof_var) in &allof_bools {
-        let allof_s_calls = &s_calls_grouped[allofs_var_name];
+                if req_var_refs.len() > 1 {
+                    for _ in &req_var_refs {
+                        coeffs.push(1);
+                    }
+
+                    let at_least_one_request =
+                        req_var_refs[0].pb_ge(&req_var_refs[1..req_var_refs.len()], coeffs, 1);
 
-        let mut s_call_vars = Vec::new();
-        for var_name in allof_s_calls {
-            s_call_vars.push(&s_call_bools[var_name].0);
+                    constraints.push((
+                        Constraint::SomeRequest(s_call.clone()),
+                        offer_clause.implies(&at_least_one_request),
+                    ));
+                } else {
+                    // only one request so no need for a pb. must be scheduled
+                    constraints.push((
+                        Constraint::SomeRequest(s_call.clone()),
+                        offer_clause.implies(req_var_refs[0]),
+                    ));
+                }
+            }
         }
 
-        let mut coeffs = Vec::new();
-        for _ in allof_s_calls {
-            coeffs.push(1);
+        if !requests.is_empty() && !offer_ids.is_empty() {
+            constraints.extend(self.pairing_constraints(&requests, &offer_ids));
         }
+        constraints.extend(self.payment_constraints(s_call, &req_ids, &offer_ids));
 
-        let allof_scall_clause2 =
-            s_call_vars[0].pb_eq(&s_call_vars[1..s_call_vars.len()], coeffs, 0);
+        constraints
+    }
 
-        solver.assert(&allof_var.not().implies(&allof_scall_clause2));
+    /// Defines the payments of the requests and offers of one service
+    /// instance: each pair of them is a match, for which the request pays the
+    /// price of the regid and the offer receives it. Wildcard requests resolved
+    /// to the instance are among `req_ids` but pay through the wildcard, see
+    /// `wildcard_constraints`.
+    fn payment_constraints(
+        &self,
+        s_call: &ServiceCall,
+        req_ids: &[ServiceCallId],
+        offer_ids: &[ServiceCallId],
+    ) -> Vec<(Constraint, Ast<'ctx>)> {
+        let mut constraints = Vec::new();
+
+        for req_id in req_ids {
+            if let Some(payment) = self.s_call_payments.get(req_id) {
+                let pairs = offer_ids.iter().filter_map(|offer_id| {
+                    self.match_bools.get(&(req_id.clone(), offer_id.clone()))
+                });
+                let paid = self.paid_per_match(&s_call.regid, pairs);
+                constraints.push((Constraint::Payment(req_id.clone()), payment._eq(&paid)));
+            }
+        }
+
+        for offer_id in offer_ids {
+            let pairs = req_ids
+                .iter()
+                .filter_map(|req_id| self.match_bools.get(&(req_id.clone(), offer_id.clone())));
+            let received = self
+                .amount(0)
+                .sub(&[&self.paid_per_match(&s_call.regid, pairs)]);
+            constraints.push((
+                Constraint::Payment(offer_id.clone()),
+                self.s_call_payments[offer_id]._eq(&received),
+            ));
+        }
+
+        constraints
     }
 
-    println!("allof service_call negation clause built");
+    /// The price of `regid` once for each of `matches` that holds
+    fn paid_per_match<'a, I>(&self, regid: &str, matches: I) -> Ast<'ctx>
+    where
+        I: IntoIterator<Item = &'a Ast<'ctx>>,
+        'ctx: 'a,
+    {
+        let price = &self.s_call_prices[regid];
+        let mut paid = self.amount(0);
+        for matched in matches {
+            paid = paid.add(&[&matched.ite(price, &self.amount(0))]);
+        }
 
-    // request and offer clauses
+        paid
+    }
 
-    // group by requests and offers
-    let mut request_s_calls = HashMap::new();
-    let mut offer_s_calls = HashMap::new();
+    /// Creates a literal for every wildcard request of `s_call`'s regid that
+    /// is true when the wildcard is served by this instance
+    fn resolve_wildcards(&mut self, s_call: &ServiceCall) -> Vec<(ServiceCallId, Ast<'ctx>)> {
+        let ctx = self.ctx;
+        let wildcard = ServiceCall::wildcard(s_call.regid.clone());
+
+        let mut resolved = Vec::new();
+        for wildcard_id in self.requests.get(&wildcard).cloned().unwrap_or_default() {
+            let resolved_id = ServiceCallId {
+                allof: wildcard_id.allof,
+                call: s_call.clone(),
+            };
+            let var_name = self.names.name(VarId::Wildcard(resolved_id.clone()));
+            let var = ctx.named_bool_const(var_name.as_str());
+
+            self.wildcard_bools.insert(resolved_id.clone(), var.clone());
+            resolved.push((resolved_id, var));
+        }
 
-    for (s_call_varname, (s_call_var, is_request)) in &s_call_bools {
-        let splits: Vec<&str> = s_call_varname.split("-").collect();
-        let mut s_call_portion = Vec::new();
+        resolved
+    }
 
-        for s in splits[2..splits.len()].iter() {
-            s_call_portion.push(s.to_string());
+    /// A scheduled wildcard request is resolved to exactly one offered
+    /// instance, an unscheduled one to none
+    fn wildcard_constraints(&self, wildcard: &ServiceCall) -> Vec<(Constraint, Ast<'ctx>)> {
+        let mut constraints = Vec::new();
+        let no_ids = Vec::new();
+
+        for wildcard_id in self.requests.get(wildcard).unwrap_or(&no_ids) {
+            let wildcard_var = &self.s_call_bools[wildcard_id].0;
+
+            let mut resolved_ids: Vec<&ServiceCallId> = self
+                .wildcard_bools
+                .keys()
+                .filter(|id| id.allof == wildcard_id.allof && id.call.regid == wildcard.regid)
+                .collect();
+            resolved_ids.sort();
+
+            let resolved_vars: Vec<&Ast<'ctx>> = resolved_ids
+                .into_iter()
+                .map(|id| &self.wildcard_bools[id])
+                .collect();
+
+            // served by one instance at most, all sharing the regid's price
+            let paid = self.paid_per_match(&wildcard.regid, resolved_vars.iter().cloned());
+            constraints.push((
+                Constraint::Payment(wildcard_id.clone()),
+                self.s_call_payments[wildcard_id]._eq(&paid),
+            ));
+
+            let must_resolve = !self.rules.allow_unserved_requests;
+            if resolved_vars.is_empty() {
+                if must_resolve {
+                    constraints
+                        .push((Constraint::NoOffer(wildcard_id.clone()), wildcard_var.not()));
+                }
+                continue;
+            }
+            constraints.push((
+                Constraint::AnyInstance(wildcard_id.clone()),
+                self.one_if(wildcard_var, &resolved_vars, must_resolve),
+            ));
         }
 
-        let s_call_instance_id_bits: String = s_call_portion.join("-").into();
+        constraints
+    }
 
-        if *is_request {
-            let entry = request_s_calls
-                .entry(s_call_instance_id_bits.clone())
-                .or_insert(Vec::new());
-            entry.push(s_call_var);
-        } else {
-            let entry = offer_s_calls
-                .entry(s_call_instance_id_bits.clone())
-                .or_insert(Vec::new());
-            entry.push(s_call_var);
+    /// With `exact`, exactly one of `vars` holds if `guard` does and none
+    /// otherwise. Without, at most one holds and only if `guard` does.
+    fn one_if(&self, guard: &Ast<'ctx>, vars: &[&Ast<'ctx>], exact: bool) -> Ast<'ctx> {
+        if exact {
+            // exactly one of (not guard, var_1, .., var_n)
+            let coeffs = vec![1; vars.len() + 1];
+            return guard.not().pb_eq(vars, coeffs, 1);
         }
+
+        let coeffs = vec![1; vars.len()];
+        let at_most_one = vars[0].pb_le(&vars[1..], coeffs, 1);
+        let any = vars[0].or(&vars[1..]);
+
+        at_most_one.and(&[&any.implies(guard)])
     }
 
-    // if a request has been scheduled, exactly one offer must be scheduled for it
-    for (s_call_instance, s_call_var) in &request_s_calls {
-        println!("handling Request: {}", s_call_instance);
-        let maybe_offer_scall_vars = &offer_s_calls.get(s_call_instance);
+    /// `s_calls` and the service calls whose matching constraints depend on
+    /// them, in the order to build them in: instances first, wildcards last
+    pub fn matching_order<'a, I>(&self, s_calls: I) -> Vec<ServiceCall>
+    where
+        I: IntoIterator<Item = &'a ServiceCall>,
+    {
+        let mut ordered = Vec::new();
+
+        for s_call in s_calls {
+            let wildcard = ServiceCall::wildcard(s_call.regid.clone());
+
+            if s_call.is_wildcard() {
+                // a new wildcard needs a literal for every offered instance
+                let instances = self
+                    .offers
+                    .keys()
+                    .filter(|offer| offer.regid == s_call.regid);
+                ordered.extend(instances.cloned());
+            } else if !self.requests.contains_key(&wildcard) {
+                ordered.push(s_call.clone());
+                continue;
+            }
+
+            // instance literals were rebuilt, so are the wildcard clauses
+            ordered.push(s_call.clone());
+            ordered.push(wildcard);
+        }
 
-        if maybe_offer_scall_vars.is_none() {
-            // then this service call set can basically not be scheduled
-            println!("Found no offer");
-            for var in s_call_var {
-                solver.assert(&var.not());
+        ordered.sort_by(|a, b| (a.is_wildcard(), a).cmp(&(b.is_wildcard(), b)));
+        ordered.dedup();
+
+        ordered
+    }
+
+    /// Pairs every request in `requests` with the offer serving it, so that a
+    /// schedule tells who serves whom
+    fn pairing_constraints(
+        &mut self,
+        requests: &[(ServiceCallId, Ast<'ctx>)],
+        offer_ids: &[ServiceCallId],
+    ) -> Vec<(Constraint, Ast<'ctx>)> {
+        let ctx = self.ctx;
+        let mut constraints = Vec::new();
+
+        // offer -> pair variables of the requests it may serve
+        let mut offer_pairs: Vec<Vec<Ast<'ctx>>> = vec![Vec::new(); offer_ids.len()];
+
+        for (req_id, req_var) in requests {
+            let mut pair_vars = Vec::new();
+            for (k, offer_id) in offer_ids.iter().enumerate() {
+                let pair_name = self
+                    .names
+                    .name(VarId::Match(req_id.clone(), offer_id.clone()));
+                let pair_var = ctx.named_bool_const(pair_name.as_str());
+
+                self.match_bools
+                    .insert((req_id.clone(), offer_id.clone()), pair_var.clone());
+                offer_pairs[k].push(pair_var.clone());
+                pair_vars.push(pair_var);
             }
-        } else {
-            println!("Found an offer");
-            let offer_scall_vars = maybe_offer_scall_vars.unwrap();
 
-            let mut req_var_refs = Vec::new();
-            for x in s_call_var {
-                req_var_refs.push(*x);
+            let pair_refs: Vec<&Ast<'ctx>> = pair_vars.iter().collect();
+            constraints.push((
+                Constraint::PairedRequest(req_id.clone()),
+                self.one_if(req_var, &pair_refs, !self.rules.allow_unserved_requests),
+            ));
+        }
+
+        for (offer_id, pair_vars) in offer_ids.iter().zip(offer_pairs) {
+            let pair_refs: Vec<&Ast<'ctx>> = pair_vars.iter().collect();
+            let any_pair = pair_refs[0].or(&pair_refs[1..]);
+            let offer_var = &self.s_call_bools[offer_id].0;
+            let paired_offer = if self.rules.allow_unsold_offers {
+                any_pair.implies(offer_var)
+            } else {
+                any_pair._eq(offer_var)
+            };
+            constraints.push((Constraint::PairedOffer(offer_id.clone()), paired_offer));
+
+            if let Some(&capacity) = self.offer_capacities.get(offer_id) {
+                let coeffs = vec![1; pair_refs.len()];
+                constraints.push((
+                    Constraint::Capacity(offer_id.clone()),
+                    pair_refs[0].pb_le(&pair_refs[1..], coeffs, capacity as i32),
+                ));
             }
-            let request_clause = req_var_refs[0].or(&req_var_refs[1..req_var_refs.len()]);
+        }
+
+        constraints
+    }
+
+    /// Units requested and offered of every service, over the scheduled allofs,
+    /// balance. Like for service calls, the rules may leave units unsold or
+    /// unserved.
+    pub fn balance_constraints(&self) -> Vec<(Constraint, Ast<'ctx>)> {
+        self.balance_constraints_of(|_| true)
+    }
 
-            let mut offer_var_refs = Vec::new();
-            for x in offer_scall_vars {
-                offer_var_refs.push(*x);
+    /// `balance_constraints` of the services `wanted` accepts
+    pub fn balance_constraints_of<F>(&self, wanted: F) -> Vec<(Constraint, Ast<'ctx>)>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut services: Vec<&String> = self
+            .unit_requests
+            .keys()
+            .chain(self.unit_offers.keys())
+            .filter(|service| wanted(service))
+            .collect();
+        services.sort();
+        services.dedup();
+
+        let mut constraints = Vec::new();
+        for service in services {
+            let requests = self.unit_requests.get(service);
+            let offers = self.unit_offers.get(service);
+
+            // every unit traded is bought and sold. The side that may not be
+            // left over trades all the units of its scheduled allofs.
+            let mut balance = vec![self.traded_units(requests)._eq(&self.traded_units(offers))];
+            if !self.rules.allow_unserved_requests {
+                balance.extend(self.traded_in_full(requests));
+            }
+            if !self.rules.allow_unsold_offers {
+                balance.extend(self.traded_in_full(offers));
             }
 
-            // clause needed if multiple offers exist for this one request
-            if offer_s_calls.len() > 1 {
-                let mut coeffs = Vec::<i32>::new();
-                for _ in offer_scall_vars {
-                    coeffs.push(1);
-                }
+            let balance: Vec<&Ast<'ctx>> = balance.iter().collect();
+            constraints.push((
+                Constraint::Balance(service.clone()),
+                balance[0].and(&balance[1..]),
+            ));
+        }
+
+        constraints
+    }
+
+    /// Sum of the units traded by `entries`
+    fn traded_units(&self, entries: Option<&Vec<(AllOfId, u32, Ast<'ctx>)>>) -> Ast<'ctx> {
+        let mut units = self.ctx.from_i64(0);
+        for (_, _, traded) in entries.into_iter().flatten() {
+            units = units.add(&[traded]);
+        }
 
-                let one_offer_clause =
-                    offer_var_refs[0].pb_eq(&offer_var_refs[1..offer_scall_vars.len()], coeffs, 1);
+        units
+    }
 
-                solver.assert(&request_clause.implies(&one_offer_clause));
+    /// Each of `entries` trades all its units if its allof is scheduled
+    fn traded_in_full(&self, entries: Option<&Vec<(AllOfId, u32, Ast<'ctx>)>>) -> Vec<Ast<'ctx>> {
+        let ctx = self.ctx;
+
+        entries
+            .into_iter()
+            .flatten()
+            .map(|&(allof_id, quantity, ref traded)| {
+                let units = self.allof_bools[&allof_id]
+                    .ite(&ctx.from_i64(i64::from(quantity)), &ctx.from_i64(0));
+                traded._eq(&units)
+            })
+            .collect()
+    }
 
-                let mut coeffs2 = Vec::<i32>::new();
-                for _ in offer_scall_vars {
-                    coeffs2.push(1);
+    /// Budget and minimum revenue of every party in `market`, over the net
+    /// costs of the scheduled allofs of the commitments naming it
+    pub fn party_constraints(
+        &self,
+        market: &Market,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError> {
+        self.party_constraints_of(market, |_| true)
+    }
+
+    /// `party_constraints` of the parties `wanted` accepts
+    pub fn party_constraints_of<F>(
+        &self,
+        market: &Market,
+        wanted: F,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError>
+    where
+        F: Fn(&str) -> bool,
+    {
+        // party -> (its commit variables, net costs of its allofs)
+        let mut party_vars = BTreeMap::new();
+        let mut commit_ids: Vec<&CommitId> = self
+            .commit_parties
+            .iter()
+            .filter(|&(_, party)| wanted(party))
+            .map(|(commit_id, _)| commit_id)
+            .collect();
+        commit_ids.sort();
+        for commit_id in commit_ids {
+            let party = self.commit_parties[commit_id].as_str();
+            if !market.parties.contains_key(party) {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} names unknown party {}",
+                    commit_id, party
+                )));
+            }
+
+            let entry = party_vars.entry(party).or_insert((Vec::new(), Vec::new()));
+            entry.0.push(&self.commit_bools[commit_id]);
+        }
+
+        let mut allof_ids: Vec<&AllOfId> = self.allof_costs.keys().collect();
+        allof_ids.sort();
+        for allof_id in allof_ids {
+            if let Some(party) = self.commit_parties.get(&allof_id.commit) {
+                if let Some(entry) = party_vars.get_mut(party.as_str()) {
+                    entry.1.push(&self.allof_costs[allof_id]);
                 }
             }
-            // if only one offer exists however, use this clause
-            else {
-                solver.assert(&request_clause.implies(offer_var_refs[0]));
+        }
+
+        let mut constraints = Vec::new();
+        for (party, (commit_vars, allof_costs)) in party_vars {
+            let mut net_cost = self.amount(0);
+            for allof_cost in allof_costs {
+                net_cost = net_cost.add(&[allof_cost]);
+            }
+
+            let wallet = &market.parties[party];
+            constraints.push((
+                Constraint::Budget(party.to_string()),
+                net_cost.le(&self.amount(wallet.budget.minor())),
+            ));
+            if let Some(min_revenue) = wallet.min_revenue {
+                let max_net_cost = min_revenue.checked_neg().ok_or_else(|| {
+                    ScheduleError::InvalidInput(format!(
+                        "minimum revenue {} of party {} is out of range",
+                        min_revenue, party
+                    ))
+                })?;
+                // revenue is the negated net cost. A party none of whose
+                // commitments is scheduled earns nothing and is left alone.
+                let scheduled = commit_vars[0].or(&commit_vars[1..]);
+                constraints.push((
+                    Constraint::MinRevenue(party.to_string()),
+                    scheduled.implies(&net_cost.le(&self.amount(max_net_cost.minor()))),
+                ));
             }
         }
+
+        Ok(constraints)
     }
 
-    println!("only one offer clause built");
+    /// Floor and cap of every price, from the catalogue in `market`. Prices
+    /// the catalogue does not bound from below are non-negative unless the
+    /// rules allow otherwise. A price stays on its tick, the catalogue's or
+    /// else that of the pricing rule, a whole unit for integer pricing, and on
+    /// one of its levels if it has any.
+    pub fn price_constraints(
+        &self,
+        market: &Market,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError> {
+        self.price_constraints_of(market, |_| true)
+    }
 
-    // if a service call is being offered, then at least one request must exist for it
-    for (s_call_instance, s_call_var) in offer_s_calls {
-        let maybe_req_scall_vars = &request_s_calls.get(&s_call_instance);
+    /// `price_constraints` of the services `wanted` accepts
+    pub fn price_constraints_of<F>(
+        &self,
+        market: &Market,
+        wanted: F,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let market_tick = match market.rules.pricing {
+            Pricing::Tick(tick) => Some(tick),
+            Pricing::Integer => Some(Money::from(1)),
+            Pricing::Rational => None,
+        };
+        let default_floor = if market.rules.allow_negative_prices {
+            None
+        } else {
+            Some(Money::zero())
+        };
+
+        let mut regids: Vec<&String> = self
+            .s_call_prices
+            .keys()
+            .filter(|regid| wanted(regid))
+            .collect();
+        regids.sort();
+
+        let mut constraints = Vec::new();
+        for regid in regids {
+            let price = &self.s_call_prices[regid];
+            let bounds = market.prices.get(regid).cloned().unwrap_or_default();
+
+            if let (Some(min), Some(max)) = (bounds.min, bounds.max) {
+                if min > max {
+                    return Err(ScheduleError::InvalidInput(format!(
+                        "price floor {} of {} is above its cap {}",
+                        min, regid, max
+                    )));
+                }
+            }
 
-        if maybe_req_scall_vars.is_none() {
-            // do not schedule this service call
-            for var in s_call_var {
-                solver.assert(&var.not());
+            if let Some(min) = bounds.min.or(default_floor) {
+                constraints.push((
+                    Constraint::PriceFloor(regid.clone()),
+                    price.ge(&self.amount(min.minor())),
+                ));
+            }
+            if let Some(max) = bounds.max {
+                constraints.push((
+                    Constraint::PriceCap(regid.clone()),
+                    price.le(&self.amount(max.minor())),
+                ));
+            }
+            if let Some(tick) = bounds.tick.or(market_tick) {
+                if tick <= Money::zero() {
+                    return Err(ScheduleError::InvalidInput(format!(
+                        "price tick {} of {} is not positive",
+                        tick, regid
+                    )));
+                }
+                constraints.push((
+                    Constraint::PriceTick(regid.clone()),
+                    self.on_tick(price, tick),
+                ));
             }
+            if !bounds.levels.is_empty() {
+                let levels: Vec<Ast<'ctx>> = bounds
+                    .levels
+                    .iter()
+                    .map(|level| price._eq(&self.amount(level.minor())))
+                    .collect();
+                let levels: Vec<&Ast<'ctx>> = levels.iter().collect();
+                constraints.push((
+                    Constraint::PriceLevel(regid.clone()),
+                    levels[0].or(&levels[1..]),
+                ));
+            }
+        }
+
+        Ok(constraints)
+    }
+
+    /// `price` is a whole multiple of `tick`
+    fn on_tick(&self, price: &Ast<'ctx>, tick: Money) -> Ast<'ctx> {
+        let tick = self.amount(tick.minor());
+        if self.rules.pricing.is_rational() {
+            let ticks = price.div(&tick);
+            ticks.to_int().to_real()._eq(&ticks)
         } else {
-            let mut coeffs = Vec::<i32>::new();
-            let req_scall_vars = maybe_req_scall_vars.unwrap();
+            price.modulo(&tick)._eq(&self.amount(0))
+        }
+    }
+
+    /// The links of `market` over the commit variables
+    pub fn link_constraints(
+        &self,
+        market: &Market,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError> {
+        self.link_constraints_of(market, |_| true)
+    }
 
-            let mut req_var_refs = Vec::new();
-            for x in req_scall_vars {
-                req_var_refs.push(*x);
+    /// `link_constraints` of the links whose index `wanted` accepts
+    pub fn link_constraints_of<F>(
+        &self,
+        market: &Market,
+        wanted: F,
+    ) -> Result<Vec<(Constraint, Ast<'ctx>)>, ScheduleError>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut constraints = Vec::new();
+
+        for (i, link) in market.links.iter().enumerate() {
+            if !wanted(i) {
+                continue;
             }
+            let link_clause = match *link {
+                Link::Requires { commit, on } => {
+                    let vars = self.linked_vars(i, &[commit, on])?;
+                    vars[0].implies(vars[1])
+                }
+                Link::Excludes { ref commits } => {
+                    let vars = self.linked_vars(i, commits)?;
+                    let coeffs = vec![1; vars.len()];
+                    vars[0].pb_le(&vars[1..], coeffs, 1)
+                }
+                Link::AllOrNone { ref commits } => {
+                    let vars = self.linked_vars(i, commits)?;
+                    let equal: Vec<Ast<'ctx>> =
+                        vars[1..].iter().map(|var| vars[0]._eq(var)).collect();
+                    let equal_refs: Vec<&Ast<'ctx>> = equal.iter().collect();
+                    equal_refs[0].and(&equal_refs[1..])
+                }
+            };
 
-            let mut offer_var_refs = Vec::new();
-            for x in &s_call_var {
-                offer_var_refs.push(*x);
+            constraints.push((Constraint::Link(i), link_clause));
+        }
+
+        Ok(constraints)
+    }
+
+    /// Commit variables of the commitments of link `index`
+    fn linked_vars(
+        &self,
+        index: usize,
+        commit_ids: &[CommitId],
+    ) -> Result<Vec<&Ast<'ctx>>, ScheduleError> {
+        let mut distinct = commit_ids.to_vec();
+        distinct.sort();
+        distinct.dedup();
+        if distinct.len() < 2 || distinct.len() != commit_ids.len() {
+            return Err(ScheduleError::InvalidInput(format!(
+                "link {} needs at least two distinct commitments",
+                index
+            )));
+        }
+
+        commit_ids
+            .iter()
+            .map(|commit_id| {
+                self.commit_bools.get(commit_id).ok_or_else(|| {
+                    ScheduleError::InvalidInput(format!(
+                        "link {} names {}, which does not exist",
+                        index, commit_id
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// At least `n` commitments are scheduled
+    pub fn min_commits(&self, n: u32) -> Ast<'ctx> {
+        let commit_vars_list = self.commit_vars();
+
+        let mut coeffs = Vec::new();
+        for _ in 0..commit_vars_list.len() {
+            coeffs.push(1);
+        }
+
+        commit_vars_list[0].pb_ge(
+            &commit_vars_list[1..commit_vars_list.len()],
+            coeffs,
+            n as i32,
+        )
+    }
+
+    /// Weighted number of scheduled commitments, one sum per tier, most
+    /// important tier first. The sums share the sort of the prices so that
+    /// every objective is compared alike.
+    pub fn tiered_objectives(&self) -> Vec<Ast<'ctx>> {
+        // untiered commitments sort after every tier
+        let mut tiers = BTreeMap::new();
+        for (commit_id, &(weight, tier)) in &self.commit_values {
+            let entry = tiers.entry((tier.is_none(), tier)).or_insert(Vec::new());
+            entry.push((*commit_id, weight));
+        }
+
+        let mut objectives = Vec::new();
+        for (_, mut tier_commits) in tiers {
+            tier_commits.sort();
+
+            let mut n_commits = self.amount(0);
+            for (commit_id, weight) in tier_commits {
+                let commit_bool = &self.commit_bools[&commit_id];
+                n_commits =
+                    n_commits.add(&[&commit_bool.ite(&self.amount(weight), &self.amount(0))]);
             }
+            objectives.push(n_commits);
+        }
 
-            let offer_clause = offer_var_refs[0].or(&offer_var_refs[1..offer_var_refs.len()]);
+        objectives
+    }
 
-            if req_scall_vars.len() > 1 {
-                for _ in req_scall_vars {
-                    coeffs.push(1);
-                }
+    /// Total slack left under the cost ceilings of the scheduled allofs, the
+    /// gains from trade of a schedule
+    pub fn surplus(&self) -> Ast<'ctx> {
+        let mut allof_ids: Vec<&AllOfId> = self.allof_surplus.keys().collect();
+        allof_ids.sort();
 
-                let at_least_one_request =
-                    req_var_refs[0].pb_ge(&req_var_refs[1..req_var_refs.len()], coeffs, 1);
+        let mut surplus = self.amount(0);
+        for allof_id in allof_ids {
+            surplus = surplus.add(&[&self.allof_surplus[allof_id]]);
+        }
 
-                solver.assert(&offer_clause.implies(&at_least_one_request));
-            } else {
-                // only one request so no need for a pb. must be scheduled
-                solver.assert(&offer_clause.implies(req_var_refs[0]));
+        surplus
+    }
+
+    /// The objectives to maximize for `objective`, most important first
+    pub fn objectives_for(&self, objective: Objective) -> Vec<Ast<'ctx>> {
+        match objective {
+            Objective::Commitments => self.tiered_objectives(),
+            Objective::Surplus => vec![self.surplus()],
+            Objective::CommitmentsThenSurplus => {
+                let mut objectives = self.tiered_objectives();
+                objectives.push(self.surplus());
+                objectives
+            }
+        }
+    }
+
+    /// The objectives deciding ties under `tie_break`, most important first,
+    /// none if the solver decides them
+    pub fn tie_break_objective(&self, tie_break: TieBreak, market: &Market) -> Vec<Ast<'ctx>> {
+        let mut commit_ids: Vec<&CommitId> = self.commit_bools.keys().collect();
+        commit_ids.sort();
+
+        match tie_break {
+            TieBreak::Solver => Vec::new(),
+            TieBreak::Lottery { seed } => {
+                let tickets = commit_ids.into_iter().map(|commit_id| {
+                    // 1..=2^16, keyed by the commitment's index in the input,
+                    // so reordering the input draws new tickets
+                    let ticket = lottery_draw(seed, commit_id.0 as u64) % (1 << 16) + 1;
+                    (commit_id, ticket as i64)
+                });
+                vec![self.scheduled_sum(tickets)]
+            }
+            TieBreak::RoundRobin => {
+                let waiting = commit_ids.into_iter().filter_map(|commit_id| {
+                    let party = self.commit_parties.get(commit_id)?;
+                    let wallet = market.parties.get(party)?;
+                    Some((commit_id, i64::from(wallet.rounds_waiting)))
+                });
+                vec![self.scheduled_sum(waiting)]
+            }
+            TieBreak::MaxMin => {
+                let mut party_commits = BTreeMap::new();
+                for commit_id in commit_ids {
+                    if let Some(party) = self.commit_parties.get(commit_id) {
+                        let entry = party_commits.entry(party).or_insert(Vec::new());
+                        entry.push((commit_id, 1));
+                    }
+                }
+
+                let counts = party_commits
+                    .into_values()
+                    .map(|commits| self.scheduled_sum(commits))
+                    .collect();
+                leximin(counts)
             }
         }
     }
-    println!("at least one request clause built");
 
-    // schedule must be non-trivial - at least one commit
-    let mut commit_vars_list = Vec::new();
-    for (var_name, commit_var) in &commit_bools {
-        commit_vars_list.push(commit_var);
+    /// Sum of the values of the scheduled commitments
+    fn scheduled_sum<'a, I>(&self, values: I) -> Ast<'ctx>
+    where
+        I: IntoIterator<Item = (&'a CommitId, i64)>,
+    {
+        let mut sum = self.amount(0);
+        for (commit_id, value) in values {
+            let commit_bool = &self.commit_bools[commit_id];
+            sum = sum.add(&[&commit_bool.ite(&self.amount(value), &self.amount(0))]);
+        }
+
+        sum
     }
 
-    let mut coeffs = Vec::new();
-    for _ in 0..commit_vars_list.len() {
-        coeffs.push(1);
+    /// `minor` in the sort of the prices: an integer, or a real under
+    /// rational pricing
+    fn amount(&self, minor: i64) -> Ast<'ctx> {
+        let amount = self.ctx.from_i64(minor);
+        if self.rules.pricing.is_rational() {
+            amount.to_real()
+        } else {
+            amount
+        }
     }
 
-    // make sure that at least one commitment is scheduled
-    let at_least_one_commit =
-        commit_vars_list[0].pb_ge(&commit_vars_list[1..commit_vars_list.len()], coeffs, 1);
-    solver.assert(&at_least_one_commit);
+    /// An exact value in the sort of the prices, such as a price or objective
+    /// value read off a model
+    pub fn constant(&self, value: Fraction) -> Ast<'ctx> {
+        match value.to_integer() {
+            Some(n) => self.amount(n),
+            None => self.amount(value.numer()).div(&self.amount(value.denom())),
+        }
+    }
 
-    // make sure that the cost ceilings are respected
-    println!("respectin cost ceilings");
-    for (i, commit) in commits.iter().enumerate() {
-        for (j, allof) in commit.allofs.iter().enumerate() {
-            let cost_ceil = ctx.from_i64(allof.cost_ceil);
-            let allof_name = format!("{}-{}", i, j);
-            //let mut s_call_costs = Vec::new();
+    /// Commit variables in commit order
+    fn commit_vars(&self) -> Vec<&Ast<'ctx>> {
+        let mut commit_ids: Vec<&CommitId> = self.commit_bools.keys().collect();
+        commit_ids.sort();
 
-            let s_calls = s_calls_grouped.get(&allof_name).unwrap();
-            let mut allof_costs = ctx.from_i64(0);
+        commit_ids
+            .into_iter()
+            .map(|id| &self.commit_bools[id])
+            .collect()
+    }
 
-            for s_call_var_name in s_calls {
-                let (_s_call_var, is_request) = s_call_bools.get(s_call_var_name).unwrap();
-                let mut coeff;
+    fn s_call_vars(&self, s_call_ids: &[ServiceCallId]) -> Vec<&Ast<'ctx>> {
+        self.s_call_vars_in(&self.s_call_bools, s_call_ids)
+    }
 
-                if *is_request {
-                    coeff = ctx.from_i64(1);
-                } else {
-                    coeff = ctx.from_i64(-1);
-                }
+    fn s_call_vars_in<'a>(
+        &self,
+        s_call_bools: &'a HashMap<ServiceCallId, (Ast<'ctx>, bool)>,
+        s_call_ids: &[ServiceCallId],
+    ) -> Vec<&'a Ast<'ctx>> {
+        s_call_ids.iter().map(|id| &s_call_bools[id].0).collect()
+    }
+}
 
-                let s_call_splits: Vec<&str> = s_call_var_name.split("-").collect();
-                let s_call_portion: Vec<&str> = s_call_splits[2..s_call_splits.len() - 1].to_vec();
-                let mut s_call_portion_strings = Vec::new();
+/// Asserts every constraint of `encoding` and maximizes the weighted number of
+/// scheduled commitments, tier by tier
+pub fn build_optimizer<'ctx>(ctx: &'ctx Context, encoding: &Encoding<'ctx>) -> Optimize<'ctx> {
+    let solver = Optimize::new(ctx);
+    for (_, constraint) in &encoding.constraints {
+        solver.assert(constraint);
+    }
 
-                for s in s_call_portion.iter() {
-                    s_call_portion_strings.push(s.to_string());
-                }
+    // maximize commitments scheduled. Z3 combines several objectives
+    // lexicographically in the order they are added.
+    for objective in &encoding.objectives {
+        solver.maximize(objective);
+    }
 
-                let s_call_str = s_call_portion_strings.join("-");
+    solver
+}
+
+/// Reads the scheduled commits, allofs, service calls and prices off a model
+pub fn read_schedule<'ctx>(
+    model: &Model<'ctx>,
+    encoding: &Encoding<'ctx>,
+) -> Result<Schedule, ScheduleError> {
+    let mut schedule = Schedule::sat();
+
+    for objective in &encoding.objectives {
+        schedule.objective.push(eval_fraction(model, objective)?);
+    }
+
+    let mut commit_ids: Vec<&CommitId> = encoding.commit_bools.keys().collect();
+    commit_ids.sort();
+    for commit_id in commit_ids {
+        if eval_bool(model, &encoding.commit_bools[commit_id])? {
+            schedule.commits.push(*commit_id);
+        }
+    }
+
+    let mut allof_ids: Vec<&AllOfId> = encoding.allof_bools.keys().collect();
+    allof_ids.sort();
+    for allof_id in allof_ids {
+        if eval_bool(model, &encoding.allof_bools[allof_id])? {
+            schedule.allofs.insert(allof_id.commit, allof_id.allof);
+        }
+    }
 
-                let cost_var = s_call_prices.get(&s_call_str).unwrap();
+    let mut s_call_ids: Vec<&ServiceCallId> = encoding.s_call_bools.keys().collect();
+    s_call_ids.sort();
+    for s_call_id in s_call_ids {
+        let (ref var, is_request) = encoding.s_call_bools[s_call_id];
+        if eval_bool(model, var)? {
+            schedule.service_calls.push(ScheduledServiceCall {
+                allof: s_call_id.allof,
+                service_call: s_call_id.call.clone(),
+                is_request,
+            });
+        }
+    }
 
-                let cost_entry = cost_var.mul(&[&coeff]);
-                allof_costs = allof_costs.add(&[&cost_entry]);
+    let unit_entries = encoding
+        .unit_requests
+        .iter()
+        .map(|entries| (entries, true))
+        .chain(encoding.unit_offers.iter().map(|entries| (entries, false)));
+    for ((service, entries), is_request) in unit_entries {
+        for &(allof_id, quantity, ref traded) in entries {
+            if schedule.allofs.get(&allof_id.commit) == Some(&allof_id.allof) {
+                let traded = eval_fraction(model, traded)?
+                    .to_integer()
+                    .and_then(|units| u32::try_from(units).ok())
+                    .ok_or_else(|| {
+                        ScheduleError::Encoding(format!(
+                            "units of {} traded by {} are out of range",
+                            service, allof_id
+                        ))
+                    })?;
+                schedule.quantities.push(ScheduledQuantity {
+                    allof: allof_id,
+                    service: service.clone(),
+                    quantity,
+                    traded,
+                    is_request,
+                });
             }
-            solver.assert(&allof_costs.le(&cost_ceil));
+        }
+    }
+    schedule.quantities.sort_by(|a, b| {
+        (a.allof, &a.service, a.is_request).cmp(&(b.allof, &b.service, b.is_request))
+    });
+
+    let mut pairs: Vec<&(ServiceCallId, ServiceCallId)> = encoding.match_bools.keys().collect();
+    pairs.sort();
+    for pair in pairs {
+        if eval_bool(model, &encoding.match_bools[pair])? {
+            let (ref request, ref offer) = *pair;
+            schedule.matches.push(Match {
+                service_call: request.call.clone(),
+                request: request.allof,
+                offer: offer.allof,
+            });
+        }
+    }
+
+    for (regid, var) in &encoding.s_call_prices {
+        if let Ok(price) = eval_fraction(model, var) {
+            schedule.prices.insert(regid.clone(), Price::new(price));
         }
     }
 
-    // maximize commitments scheduled
-    let mut n_commits = ctx.from_i64(0);
+    Ok(schedule)
+}
+
+/// Rejects commitments the encoding cannot represent
+pub fn validate_commits(commits: &[Commit]) -> Result<(), ScheduleError> {
+    if commits.is_empty() {
+        return Err(ScheduleError::InvalidInput("no commitments given".into()));
+    }
+
     for (i, commit) in commits.iter().enumerate() {
-        let commit_varname = format!("{}", i);
-        let commit_bool = commit_bools.get(&commit_varname).unwrap();
+        validate_commit(CommitId(i), commit)?;
+    }
+
+    Ok(())
+}
+
+pub fn validate_commit(commit_id: CommitId, commit: &Commit) -> Result<(), ScheduleError> {
+    if let Some(weight) = commit.weight {
+        if weight <= 0 {
+            return Err(ScheduleError::InvalidInput(format!(
+                "{} has non-positive weight {}",
+                commit_id, weight
+            )));
+        }
+    }
 
-        n_commits = n_commits.add(&[&commit_bool.ite(&ctx.from_i64(1), &ctx.from_i64(0))]);
+    if commit.allofs.is_empty() {
+        return Err(ScheduleError::InvalidInput(format!(
+            "{} has no allofs",
+            commit_id
+        )));
     }
-    solver.maximize(&n_commits);
 
-    println!("{:?}", solver.check());
+    for (j, allof) in commit.allofs.iter().enumerate() {
+        let allof_id = AllOfId {
+            commit: commit_id,
+            allof: j,
+        };
+
+        let s_calls = allof.s_calls();
+        if s_calls.is_empty() && allof.quantities.is_empty() {
+            return Err(ScheduleError::InvalidInput(format!(
+                "{} has no service calls",
+                allof_id
+            )));
+        }
 
-    if solver.check() {
-        let model = solver.get_model();
-        for (name, var) in &commit_bools {
-            println!("{}: {}", name, model.eval(var).unwrap().as_bool().unwrap());
+        let mut seen = HashSet::new();
+        for &(s_call, is_request) in &s_calls {
+            if !seen.insert(s_call) {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} lists {} more than once",
+                    allof_id, s_call
+                )));
+            }
+            if s_call.is_wildcard() && !is_request {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} offers {}, only requests may leave the instance open",
+                    allof_id, s_call
+                )));
+            }
         }
 
-        for (name, var) in &allof_bools {
-            println!("{}: {}", name, model.eval(var).unwrap().as_bool().unwrap());
+        // a wildcard resolves to the id of the instance it is served by, which
+        // must not be one the allof names itself
+        for &(s_call, _) in &s_calls {
+            let wildcard = ServiceCall::wildcard(s_call.regid.as_str());
+            if !s_call.is_wildcard() && seen.contains(&wildcard) {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} lists both {} and {}",
+                    allof_id, wildcard, s_call
+                )));
+            }
         }
 
-        for (name, var) in &s_call_bools {
-            println!(
-                "{}: {}",
-                name,
-                model.eval(&var.0).unwrap().as_bool().unwrap()
-            );
+        for quantity in &allof.quantities {
+            if quantity.quantity == 0 {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} has a zero quantity of {}",
+                    allof_id, quantity.service
+                )));
+            }
         }
 
-        for (name, var) in &s_call_prices {
-            //println!("name: {}", name);
-            println!("{}: {:?}", name, model.eval(&var).unwrap().as_real());
+        for (g, group) in allof.groups.iter().enumerate() {
+            if group.k == 0 || group.k as usize > group.service_instances.len() {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "group {} of {} picks {} of {} service calls",
+                    g,
+                    allof_id,
+                    group.k,
+                    group.service_instances.len()
+                )));
+            }
+        }
+
+        for (s_call, &capacity) in &allof.capacities {
+            if !s_calls.contains(&(s_call, false)) {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} gives a capacity for {}, which it does not offer",
+                    allof_id, s_call
+                )));
+            }
+            if capacity == 0 || capacity > i32::MAX as u32 {
+                return Err(ScheduleError::InvalidInput(format!(
+                    "{} has capacity {} for {}",
+                    allof_id, capacity, s_call
+                )));
+            }
         }
     }
+
+    Ok(())
 }
 
-pub fn build_commit_level_vars<'ctx>(
-    ctx: &'ctx Context,
-    commits: &Vec<Commit>,
-) -> HashMap<String, Ast<'ctx>> {
-    let mut commit_bools = HashMap::new();
+/// An integer or real value of a model, exactly
+fn eval_fraction<'ctx>(model: &Model<'ctx>, var: &Ast<'ctx>) -> Result<Fraction, ScheduleError> {
+    let value = model.eval(var);
+    let integer = value
+        .as_ref()
+        .and_then(|v| v.as_i64())
+        .map(Fraction::from_integer);
+
+    integer
+        .or_else(|| {
+            value
+                .as_ref()
+                .and_then(|v| v.as_real())
+                .and_then(|(numer, denom)| Fraction::new(numer, denom))
+        })
+        .ok_or_else(|| ScheduleError::Encoding("could not evaluate number in model".into()))
+}
 
-    for (i, commit) in commits.iter().enumerate() {
-        let cur_commit_var_name = i.to_string();
+fn eval_bool<'ctx>(model: &Model<'ctx>, var: &Ast<'ctx>) -> Result<bool, ScheduleError> {
+    model
+        .eval(var)
+        .and_then(|v| v.as_bool())
+        .ok_or_else(|| ScheduleError::Encoding("could not evaluate boolean in model".into()))
+}
 
-        let commit_bool = ctx.named_bool_const(cur_commit_var_name.as_str());
-        commit_bools.insert(cur_commit_var_name, commit_bool);
-    }
+pub fn build_commit_level_var<'ctx>(
+    ctx: &'ctx Context,
+    commit_id: CommitId,
+    names: &mut VarTable,
+) -> Ast<'ctx> {
+    let var_name = names.name(VarId::Commit(commit_id));
 
-    commit_bools
+    ctx.named_bool_const(var_name.as_str())
 }
 
 pub fn build_allof_level_bools<'ctx>(
     ctx: &'ctx Context,
-    commits: &Vec<Commit>,
-) -> HashMap<String, Ast<'ctx>> {
+    commit_id: CommitId,
+    commit: &Commit,
+    names: &mut VarTable,
+) -> HashMap<AllOfId, Ast<'ctx>> {
     let mut allof_bools = HashMap::new();
 
-    for (i, commit) in commits.iter().enumerate() {
-        for (j, allof) in commit.allofs.iter().enumerate() {
-            let cur_allof_var_name = format!("{}-{}", i, j);
+    for j in 0..commit.allofs.len() {
+        let allof_id = AllOfId {
+            commit: commit_id,
+            allof: j,
+        };
+        let var_name = names.name(VarId::AllOf(allof_id));
 
-            let var = ctx.named_bool_const(cur_allof_var_name.as_str());
-            allof_bools.insert(cur_allof_var_name, var);
-        }
+        let var = ctx.named_bool_const(var_name.as_str());
+        allof_bools.insert(allof_id, var);
     }
 
     allof_bools
@@ -406,18 +1776,25 @@ pub fn build_allof_level_bools<'ctx>(
 
 pub fn build_scall_level_bools<'ctx>(
     ctx: &'ctx Context,
-    commits: &Vec<Commit>,
-) -> HashMap<String, (Ast<'ctx>, bool)> {
+    commit_id: CommitId,
+    commit: &Commit,
+    names: &mut VarTable,
+) -> HashMap<ServiceCallId, (Ast<'ctx>, bool)> {
     let mut scall_bools = HashMap::new();
 
-    for (i, commit) in commits.iter().enumerate() {
-        for (j, allof) in commit.allofs.iter().enumerate() {
-            for (s_call_regid, is_request) in &allof.service_instances {
-                let var_name = format!("{}-{}-{}", i, j, s_call_regid);
-
-                let var = ctx.named_bool_const(var_name.as_str());
-                scall_bools.insert(var_name, (var, *is_request));
-            }
+    for (j, allof) in commit.allofs.iter().enumerate() {
+        for (s_call, is_request) in allof.s_calls() {
+            let s_call_id = ServiceCallId {
+                allof: AllOfId {
+                    commit: commit_id,
+                    allof: j,
+                },
+                call: s_call.clone(),
+            };
+            let var_name = names.name(VarId::ServiceCall(s_call_id.clone()));
+
+            let var = ctx.named_bool_const(var_name.as_str());
+            scall_bools.insert(s_call_id, (var, is_request));
         }
     }
 
@@ -425,75 +1802,657 @@ pub fn build_scall_level_bools<'ctx>(
 }
 
 /// Result:
-/// Allof (name) -> service-call-var-name
+/// Allof -> service-call ids in that allof
 pub fn group_s_calls<'ctx>(
-    s_call_bools: &HashMap<String, (Ast<'ctx>, bool)>,
-) -> HashMap<String, Vec<String>> {
-    let mut name_group = HashMap::new();
+    s_call_bools: &HashMap<ServiceCallId, (Ast<'ctx>, bool)>,
+) -> HashMap<AllOfId, Vec<ServiceCallId>> {
+    let mut id_group = HashMap::new();
 
-    for (s_call_name, _) in s_call_bools {
-        let splits: Vec<&str> = s_call_name.split("-").collect();
-
-        let commit_id = splits[0];
-        let allof_id = splits[1];
+    for s_call_id in s_call_bools.keys() {
+        let entry = id_group.entry(s_call_id.allof).or_insert(Vec::new());
+        entry.push(s_call_id.clone());
+    }
 
-        let entry = name_group
-            .entry(format!("{}-{}", commit_id, allof_id))
-            .or_insert(Vec::new());
-        entry.push(s_call_name.clone());
+    for s_call_ids in id_group.values_mut() {
+        s_call_ids.sort();
     }
 
-    name_group
+    id_group
 }
 
 pub fn group_allofs<'ctx>(
-    allof_bools: &HashMap<String, Ast<'ctx>>,
-) -> HashMap<String, Vec<String>> {
-    let mut name_group = HashMap::new();
-
-    for (allof_name, _) in allof_bools {
-        let splits: Vec<&str> = allof_name.split("-").collect();
+    allof_bools: &HashMap<AllOfId, Ast<'ctx>>,
+) -> HashMap<CommitId, Vec<AllOfId>> {
+    let mut id_group = HashMap::new();
 
-        let commit_id = splits[0];
+    for allof_id in allof_bools.keys() {
+        let entry = id_group.entry(allof_id.commit).or_insert(Vec::new());
 
-        let entry = name_group
-            .entry(format!("{}", commit_id))
-            .or_insert(Vec::new());
+        entry.push(*allof_id)
+    }
 
-        entry.push(allof_name.clone())
+    for allof_ids in id_group.values_mut() {
+        allof_ids.sort();
     }
 
-    name_group
+    id_group
 }
 
-/// Builds a per-service call cost integer
+/// Builds a per-service call cost for every regid in `commit` that does not
+/// have one yet, a real under rational pricing and an integer otherwise
 pub fn build_count_vars<'ctx>(
     ctx: &'ctx Context,
-    commits: &Vec<Commit>,
-) -> HashMap<String, Ast<'ctx>> {
-    let mut s_call_costs = HashMap::new();
-
-    for commit in commits {
-        for allof in &commit.allofs {
-            for (s_call_instance, is_request) in &allof.service_instances {
-                // drop the instance id first
-                let splits: Vec<&str> = s_call_instance.split("-").collect();
-                let mut splits_str = Vec::new();
-                let limit = splits.len() - 1;
-
-                for (i, portion) in splits.into_iter().enumerate() {
-                    if i < limit {
-                        splits_str.push(portion.to_string());
-                    }
-                }
+    commit: &Commit,
+    pricing: Pricing,
+    names: &mut VarTable,
+    s_call_costs: &mut HashMap<String, Ast<'ctx>>,
+) {
+    for allof in &commit.allofs {
+        for (s_call, _) in allof.s_calls() {
+            if s_call_costs.contains_key(&s_call.regid) {
+                continue;
+            }
+
+            let var_name = names.name(VarId::Price(s_call.regid.clone()));
+            let s_call_price = price_const(ctx, pricing, var_name.as_str());
 
-                let s_call_portion: String = splits_str.join("-").into();
-                let s_call_price = ctx.named_int_const(s_call_portion.as_str());
+            s_call_costs.insert(s_call.regid.clone(), s_call_price);
+        }
 
-                s_call_costs.insert(s_call_portion, s_call_price);
+        for quantity in &allof.quantities {
+            if s_call_costs.contains_key(&quantity.service) {
+                continue;
             }
+
+            let var_name = names.name(VarId::Price(quantity.service.clone()));
+            let s_call_price = price_const(ctx, pricing, var_name.as_str());
+
+            s_call_costs.insert(quantity.service.clone(), s_call_price);
+        }
+    }
+}
+
+/// `values` sorted ascending, by a bubble-sort network of `min`/`max` terms.
+/// Maximizing the result lexicographically maximizes the least value, then the
+/// second least, and so on, so a value pinned at its lowest does not stop the
+/// others from being raised.
+fn leximin<'ctx>(mut values: Vec<Ast<'ctx>>) -> Vec<Ast<'ctx>> {
+    for end in (1..values.len()).rev() {
+        for i in 0..end {
+            let in_order = values[i].le(&values[i + 1]);
+            let low = in_order.ite(&values[i], &values[i + 1]);
+            let high = in_order.ite(&values[i + 1], &values[i]);
+            values[i] = low;
+            values[i + 1] = high;
+        }
+    }
+
+    values
+}
+
+/// splitmix64 of the `n`th draw after `seed`, so that lotteries are
+/// reproducible across runs and platforms
+fn lottery_draw(seed: u64, n: u64) -> u64 {
+    let mut z = seed.wrapping_add(n.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn price_const<'ctx>(ctx: &'ctx Context, pricing: Pricing, name: &str) -> Ast<'ctx> {
+    if pricing.is_rational() {
+        ctx.named_real_const(name)
+    } else {
+        ctx.named_int_const(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use settlement::settle;
+    use std::time::Duration;
+
+    /// An allof over `calls`, each a `regid-instance` that is requested
+    /// (`true`) or offered
+    pub fn allof(calls: &[(&str, bool)], cost_ceil: i32) -> AllOf {
+        AllOf {
+            service_instances: calls
+                .iter()
+                .map(|&(call, is_request)| (call.parse().unwrap(), is_request))
+                .collect(),
+            cost_ceil: Money::from(cost_ceil),
+            ..Default::default()
+        }
+    }
+
+    pub fn commit(allofs: Vec<AllOf>) -> Commit {
+        Commit {
+            allofs,
+            ..Default::default()
         }
     }
 
-    s_call_costs
+    /// A buyer of abcde-0 or efgh-0 and two sellers of abcde-0 that want at
+    /// least 50 each: the buyer and one seller clear
+    pub fn two_sellers() -> Vec<Commit> {
+        vec![
+            commit(vec![
+                allof(&[("abcde-0", true)], 100),
+                allof(&[("efgh-0", true)], 100),
+            ]),
+            commit(vec![allof(&[("abcde-0", false)], -50)]),
+            commit(vec![allof(&[("abcde-0", false)], -50)]),
+        ]
+    }
+
+    pub fn schedule_with(
+        commits: Vec<Commit>,
+        market: &Market,
+        options: &ScheduleOptions,
+    ) -> Schedule {
+        build_schedule_with(commits, market, options).unwrap()
+    }
+
+    #[test]
+    fn limits_report_an_interrupted_optimization_as_unknown() {
+        let starved = ScheduleOptions {
+            rlimit: Some(1),
+            ..Default::default()
+        };
+        let schedule = schedule_with(two_sellers(), &Market::default(), &starved);
+        assert_eq!(
+            schedule.status,
+            Status::Unknown {
+                reason: UnknownReason::ResourceLimit
+            }
+        );
+
+        let generous = ScheduleOptions {
+            timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let schedule = schedule_with(two_sellers(), &Market::default(), &generous);
+        assert_eq!(schedule.status, Status::Sat);
+        assert_eq!(schedule.commits.len(), 2);
+
+        // a proof of unsat within the timeout is no interruption
+        let no_offer = vec![commit(vec![allof(&[("abcde-0", true)], 10)])];
+        let schedule = schedule_with(no_offer, &Market::default(), &generous);
+        assert_eq!(schedule.status, Status::Unsat);
+    }
+
+    #[test]
+    fn wildcard_next_to_its_instance_is_rejected() {
+        let mut allof = AllOf::default();
+        allof
+            .service_instances
+            .insert(ServiceCall::new("abcde", "0"), true);
+        allof
+            .service_instances
+            .insert(ServiceCall::wildcard("abcde"), true);
+        let commit = Commit {
+            allofs: vec![allof],
+            ..Default::default()
+        };
+
+        assert!(validate_commit(CommitId(0), &commit).is_err());
+    }
+
+    #[test]
+    fn weights_and_tiers_decide_between_competing_sellers() {
+        let mut commits = two_sellers();
+        commits[2].weight = Some(3);
+        let schedule = schedule_with(commits.clone(), &Market::default(), &Default::default());
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(2)]);
+        assert_eq!(schedule.objective, vec![Fraction::from_integer(4)]);
+
+        // any tier outranks the untiered commitments, whatever their weight
+        commits[1].tier = Some(0);
+        let schedule = schedule_with(commits, &Market::default(), &Default::default());
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(1)]);
+        assert_eq!(
+            schedule.objective,
+            vec![Fraction::from_integer(1), Fraction::from_integer(1)]
+        );
+    }
+
+    #[test]
+    fn surplus_favors_the_seller_asking_less() {
+        let mut commits = two_sellers();
+        commits[1].allofs[0].cost_ceil = Money::from(-60);
+        commits[2].allofs[0].cost_ceil = Money::from(-10);
+        let surplus = ScheduleOptions {
+            objective: Objective::Surplus,
+            ..Default::default()
+        };
+
+        let schedule = schedule_with(commits, &Market::default(), &surplus);
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(2)]);
+
+        // the buyer values the call at 100 and the seller at 10, whatever
+        // the price between them
+        let gains = Fraction::from_integer(Money::from(90).minor());
+        assert_eq!(schedule.objective, vec![gains]);
+    }
+
+    #[test]
+    fn a_party_budget_spans_its_commitments() {
+        // one party buys two services from sellers asking 70 each
+        let mut commits = vec![
+            commit(vec![allof(&[("abcde-0", true)], 100)]),
+            commit(vec![allof(&[("efgh-0", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", false)], -70)]),
+            commit(vec![allof(&[("efgh-0", false)], -70)]),
+        ];
+        commits[0].party = Some("acme".to_string());
+        commits[1].party = Some("acme".to_string());
+
+        let mut market = Market::default();
+        let wallet = Party {
+            budget: Money::from(200),
+            min_revenue: None,
+            rounds_waiting: 0,
+        };
+        market.parties.insert("acme".to_string(), wallet);
+        let schedule = schedule_with(commits.clone(), &market, &Default::default());
+        assert_eq!(schedule.commits.len(), 4);
+
+        // each ceiling alone allows both, the budget only one
+        market.parties.get_mut("acme").unwrap().budget = Money::from(120);
+        let schedule = schedule_with(commits, &market, &Default::default());
+        assert_eq!(schedule.commits.len(), 2);
+    }
+
+    #[test]
+    fn an_offer_is_paid_once_per_request_it_serves() {
+        // a seller wanting 150 for an instance two buyers of 100 share
+        let mut commits = vec![
+            commit(vec![allof(&[("abcde-0", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", false)], -150)]),
+        ];
+        commits[2].allofs[0]
+            .capacities
+            .insert("abcde-0".parse().unwrap(), 2);
+
+        let schedule = schedule_with(commits.clone(), &Market::default(), &Default::default());
+        assert_eq!(schedule.commits.len(), 3);
+        assert_eq!(schedule.matches.len(), 2);
+
+        // the ledger pays the seller what the encoding charged for
+        let settlement = settle(&schedule, &commits).unwrap();
+        let price = schedule.prices["abcde"].to_money().unwrap();
+        assert_eq!(settlement.commits[&CommitId(0)], price);
+        assert_eq!(
+            settlement.commits[&CommitId(2)],
+            price.checked_mul(2).unwrap().checked_neg().unwrap()
+        );
+        assert!(settlement.commits[&CommitId(2)] <= Money::from(-150));
+    }
+
+    #[test]
+    fn matches_name_the_scheduled_offer() {
+        let schedule = schedule_with(two_sellers(), &Market::default(), &Default::default());
+
+        assert_eq!(schedule.matches.len(), 1);
+        let served = &schedule.matches[0];
+        assert_eq!(served.service_call, "abcde-0".parse().unwrap());
+        assert_eq!(
+            served.request,
+            AllOfId {
+                commit: CommitId(0),
+                allof: 0
+            }
+        );
+        assert!(schedule.commits.contains(&served.offer.commit));
+        assert_ne!(served.offer.commit, CommitId(0));
+    }
+
+    #[test]
+    fn an_offer_serves_up_to_its_capacity() {
+        let buyer = commit(vec![allof(&[("abcde-0", true)], 100)]);
+        let mut seller = commit(vec![allof(&[("abcde-0", false)], -50)]);
+        let mut commits = vec![buyer.clone(), buyer.clone(), buyer];
+
+        for &(capacity, served) in &[(1, 1), (2, 2), (5, 3)] {
+            seller.allofs[0]
+                .capacities
+                .insert("abcde-0".parse().unwrap(), capacity);
+            commits.truncate(3);
+            commits.push(seller.clone());
+
+            let schedule = schedule_with(commits.clone(), &Market::default(), &Default::default());
+            assert_eq!(schedule.matches.len(), served);
+            assert_eq!(schedule.commits.len(), served + 1);
+        }
+    }
+
+    /// An allof trading `quantity` units of `service` and nothing else
+    fn units(service: &str, quantity: u32, side: Side, cost_ceil: i32) -> AllOf {
+        AllOf {
+            quantities: vec![Quantity {
+                service: service.to_string(),
+                quantity,
+                side,
+            }],
+            cost_ceil: Money::from(cost_ceil),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quantities_balance_over_the_scheduled_allofs() {
+        // 3 units wanted at up to 100 in all, offered as 1 + 2
+        let commits = vec![
+            commit(vec![units("cpu", 3, Side::Request, 100)]),
+            commit(vec![units("cpu", 1, Side::Offer, -20)]),
+            commit(vec![units("cpu", 2, Side::Offer, -40)]),
+        ];
+        let schedule = schedule_with(commits.clone(), &Market::default(), &Default::default());
+        assert_eq!(schedule.commits.len(), 3);
+        assert_eq!(schedule.quantities.len(), 3);
+
+        // per unit, the sellers want at least 20 and the buyer pays at most 33
+        let price = schedule.prices["cpu"].to_money().unwrap();
+        assert!(price >= Money::from(20));
+        assert!(price.checked_mul(3).unwrap() <= Money::from(100));
+
+        // without the unit on offer the rest does not balance
+        let schedule = schedule_with(
+            vec![commits[0].clone(), commits[2].clone()],
+            &Market::default(),
+            &Default::default(),
+        );
+        assert_eq!(schedule.status, Status::Unsat);
+    }
+
+    #[test]
+    fn a_wildcard_request_is_served_by_any_instance() {
+        let commits = vec![
+            commit(vec![allof(&[("abcde-*", true)], 100)]),
+            commit(vec![allof(&[("efgh-0", false)], -50)]),
+            commit(vec![allof(&[("abcde-3", false)], -50)]),
+        ];
+
+        let schedule = schedule_with(commits.clone(), &Market::default(), &Default::default());
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(2)]);
+        assert_eq!(schedule.matches.len(), 1);
+        assert_eq!(schedule.matches[0].service_call, "abcde-3".parse().unwrap());
+
+        let schedule = schedule_with(
+            commits[..2].to_vec(),
+            &Market::default(),
+            &Default::default(),
+        );
+        assert_eq!(schedule.status, Status::Unsat);
+    }
+
+    #[test]
+    fn a_group_schedules_k_of_its_substitutes() {
+        let mut buyer = allof(&[], 100);
+        buyer.groups.push(KOf {
+            k: 2,
+            service_instances: ["abcde-0", "abcde-1", "abcde-2"]
+                .iter()
+                .map(|call| (call.parse().unwrap(), true))
+                .collect(),
+        });
+        let commits = vec![
+            commit(vec![buyer]),
+            commit(vec![allof(&[("abcde-0", false)], -10)]),
+            commit(vec![allof(&[("abcde-2", false)], -10)]),
+        ];
+
+        let schedule = schedule_with(commits.clone(), &Market::default(), &Default::default());
+        assert_eq!(schedule.commits.len(), 3);
+        let mut served: Vec<String> = schedule
+            .matches
+            .iter()
+            .map(|served| served.service_call.to_string())
+            .collect();
+        served.sort();
+        assert_eq!(served, vec!["abcde-0", "abcde-2"]);
+
+        // one substitute on offer is not enough
+        let schedule = schedule_with(
+            commits[..2].to_vec(),
+            &Market::default(),
+            &Default::default(),
+        );
+        assert_eq!(schedule.status, Status::Unsat);
+    }
+
+    #[test]
+    fn links_tie_commitments_together_or_apart() {
+        let mut market = Market::default();
+        market.links.push(Link::Requires {
+            commit: CommitId(0),
+            on: CommitId(2),
+        });
+        let schedule = schedule_with(two_sellers(), &market, &Default::default());
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(2)]);
+
+        // the buyer may deal with neither seller
+        market.links = vec![
+            Link::Excludes {
+                commits: vec![CommitId(0), CommitId(1)],
+            },
+            Link::Excludes {
+                commits: vec![CommitId(0), CommitId(2)],
+            },
+        ];
+        let schedule = schedule_with(two_sellers(), &market, &Default::default());
+        assert_eq!(schedule.status, Status::Unsat);
+    }
+
+    #[test]
+    fn unserved_requests_cost_nothing() {
+        let commits = vec![
+            commit(vec![allof(&[("abcde-0", true), ("efgh-0", true)], 100)]),
+            commit(vec![allof(&[("abcde-0", false)], -50)]),
+        ];
+        let mut market = Market::default();
+        market.prices.insert(
+            "efgh".to_string(),
+            PriceBounds {
+                min: Some(Money::from(60)),
+                ..Default::default()
+            },
+        );
+        market.rules.allow_unserved_requests = true;
+
+        // efgh-0 would take the buyer over its ceiling if it were paid for
+        let schedule = schedule_with(commits, &market, &Default::default());
+        assert_eq!(schedule.commits.len(), 2);
+        assert_eq!(schedule.matches.len(), 1);
+    }
+
+    #[test]
+    fn unsold_units_are_not_paid_for() {
+        // 5 units on offer for at least 60 in all, 2 of them wanted for 100
+        let commits = vec![
+            commit(vec![units("cpu", 2, Side::Request, 100)]),
+            commit(vec![units("cpu", 5, Side::Offer, -60)]),
+        ];
+        let mut market = Market::default();
+        market.rules.allow_unsold_offers = true;
+
+        let schedule = schedule_with(commits.clone(), &market, &Default::default());
+        assert_eq!(schedule.commits.len(), 2);
+        let traded: Vec<u32> = schedule.quantities.iter().map(|q| q.traded).collect();
+        assert_eq!(traded, vec![2, 2]);
+
+        let settlement = settle(&schedule, &commits).unwrap();
+        assert!(settlement.commits[&CommitId(1)] <= Money::from(-60));
+
+        // the 2 units sold can not fetch 120
+        let mut greedy = commits;
+        greedy[1].allofs[0].cost_ceil = Money::from(-120);
+        let schedule = schedule_with(greedy, &market, &Default::default());
+        assert_eq!(schedule.status, Status::Unsat);
+    }
+
+    #[test]
+    fn price_bounds_hold_the_price_in_range() {
+        let bounded = |min: i32, max: i32| {
+            let mut market = Market::default();
+            let bounds = PriceBounds {
+                min: Some(Money::from(min)),
+                max: Some(Money::from(max)),
+                ..Default::default()
+            };
+            market.prices.insert("abcde".to_string(), bounds);
+            schedule_with(two_sellers(), &market, &Default::default())
+        };
+
+        let schedule = bounded(60, 70);
+        let price = schedule.prices["abcde"].to_money().unwrap();
+        assert!(Money::from(60) <= price && price <= Money::from(70));
+
+        // a reserve above what the buyer pays, or a cap below what the
+        // sellers ask
+        assert_eq!(bounded(120, 150).status, Status::Unsat);
+        assert_eq!(bounded(0, 40).status, Status::Unsat);
+    }
+
+    #[test]
+    fn ticks_and_levels_pick_the_price() {
+        // the buyer pays up to 100, a seller asks 50
+        let priced = |tick: Option<i32>, levels: &[i32]| {
+            let mut market = Market::default();
+            let bounds = PriceBounds {
+                tick: tick.map(Money::from),
+                levels: levels.iter().map(|&level| Money::from(level)).collect(),
+                ..Default::default()
+            };
+            market.prices.insert("abcde".to_string(), bounds);
+            schedule_with(two_sellers(), &market, &Default::default())
+        };
+        let price = |schedule: &Schedule| schedule.prices["abcde"].to_money().unwrap();
+
+        assert_eq!(price(&priced(Some(40), &[])), Money::from(80));
+        assert_eq!(price(&priced(None, &[30, 70, 120])), Money::from(70));
+        assert_eq!(price(&priced(Some(35), &[60, 70])), Money::from(70));
+
+        // no level between what the sellers ask and what the buyer pays
+        assert_eq!(priced(None, &[30, 120]).commits, vec![]);
+    }
+
+    #[test]
+    fn cost_ceilings_keep_their_cents() {
+        let mut commits = two_sellers();
+        commits[0].allofs[0].cost_ceil = "10.50".parse().unwrap();
+        commits[1].allofs[0].cost_ceil = "-10.25".parse().unwrap();
+        commits[2].allofs[0].cost_ceil = "-10.75".parse().unwrap();
+
+        // billed in cents, so prices move by the cent
+        let mut market = Market::default();
+        market.rules.pricing = Pricing::Tick("0.01".parse().unwrap());
+        let schedule = schedule_with(commits, &market, &Default::default());
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(1)]);
+        let price = schedule.prices["abcde"].to_money().unwrap();
+        assert!("10.25".parse::<Money>().unwrap() <= price);
+        assert!(price <= "10.50".parse().unwrap());
+    }
+
+    #[test]
+    fn integer_prices_cannot_split_an_odd_cost() {
+        // two buyers paying at most 1.5 each for a seller asking 3
+        let buyer = commit(vec![allof(&[("abcde-0", true)], 1)]);
+        let mut buyers = vec![buyer.clone(), buyer];
+        for buyer in &mut buyers {
+            buyer.allofs[0].cost_ceil = "1.5".parse().unwrap();
+        }
+        let mut seller = commit(vec![allof(&[("abcde-0", false)], -3)]);
+        seller.allofs[0]
+            .capacities
+            .insert("abcde-0".parse().unwrap(), 2);
+        let mut commits = buyers;
+        commits.push(seller);
+
+        let priced = |pricing: Pricing| {
+            let mut market = Market::default();
+            market.rules.pricing = pricing;
+            schedule_with(commits.clone(), &market, &Default::default())
+        };
+
+        assert_eq!(priced(Pricing::Integer).commits, vec![]);
+        let schedule = priced(Pricing::Rational);
+        assert_eq!(schedule.commits.len(), 3);
+        assert_eq!(
+            schedule.prices["abcde"].to_money(),
+            Some("1.5".parse().unwrap())
+        );
+    }
+
+    /// `two_sellers` with the sellers submitted by parties `a` and `b`
+    fn rival_parties(rounds_waiting: (u32, u32)) -> (Vec<Commit>, Market) {
+        let mut commits = two_sellers();
+        commits[1].party = Some("a".to_string());
+        commits[2].party = Some("b".to_string());
+
+        let mut market = Market::default();
+        for &(party, rounds_waiting) in &[("a", rounds_waiting.0), ("b", rounds_waiting.1)] {
+            let wallet = Party {
+                budget: Money::from(1000),
+                min_revenue: None,
+                rounds_waiting,
+            };
+            market.parties.insert(party.to_string(), wallet);
+        }
+        (commits, market)
+    }
+
+    #[test]
+    fn round_robin_takes_turns_between_tied_parties() {
+        let options = ScheduleOptions {
+            tie_break: TieBreak::RoundRobin,
+            ..Default::default()
+        };
+        let (commits, mut market) = rival_parties((0, 3));
+
+        // b has waited longer, then a has
+        for &seller in &[CommitId(2), CommitId(1), CommitId(2)] {
+            let schedule = schedule_with(commits.clone(), &market, &options);
+            assert_eq!(schedule.commits, vec![CommitId(0), seller]);
+            market.record_round(&schedule, &commits);
+        }
+    }
+
+    #[test]
+    fn max_min_spreads_the_schedule_over_the_parties() {
+        // a also sells efgh, to a buyer of its own
+        let (mut commits, market) = rival_parties((0, 0));
+        commits.push(commit(vec![allof(&[("efgh-0", true)], 100)]));
+        commits.push(commit(vec![allof(&[("efgh-0", false)], -50)]));
+        commits[4].party = Some("a".to_string());
+
+        let options = ScheduleOptions {
+            tie_break: TieBreak::MaxMin,
+            ..Default::default()
+        };
+        let schedule = schedule_with(commits, &market, &options);
+        assert_eq!(
+            schedule.commits,
+            vec![CommitId(0), CommitId(2), CommitId(3), CommitId(4)]
+        );
+    }
+
+    #[test]
+    fn a_lottery_is_decided_by_its_seed() {
+        let (commits, market) = rival_parties((0, 0));
+        let drawn = |seed: u64| {
+            let options = ScheduleOptions {
+                tie_break: TieBreak::Lottery { seed },
+                ..Default::default()
+            };
+            schedule_with(commits.clone(), &market, &options).commits
+        };
+
+        for seed in 0..4 {
+            assert_eq!(drawn(seed).len(), 2);
+            assert_eq!(drawn(seed), drawn(seed));
+        }
+    }
 }
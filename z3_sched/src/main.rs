@@ -18,48 +18,60 @@
 //  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 extern crate serde_json;
+extern crate z3;
 extern crate z3_sched;
 
 use std::collections::HashMap;
-use z3_sched::{build_schedule, AllOf, Commit};
+use z3::{Config, Context};
+use z3_sched::explain::{explain_exclusion, explain_unsat};
+use z3_sched::{
+    build_schedule, AllOf, Commit, CommitId, Market, Money, Schedule, ScheduleError,
+    ScheduleOptions, Scheduler, ServiceCall,
+};
 
 fn build_unsat1() -> Vec<Commit> {
     let mut allof0_sids = HashMap::new();
 
-    allof0_sids.insert("abcde-0".into(), true);
-    allof0_sids.insert("abcde-1".into(), true);
+    allof0_sids.insert(ServiceCall::new("abcde", "0"), true);
+    allof0_sids.insert(ServiceCall::new("abcde", "1"), true);
 
     let allof0 = AllOf {
         service_instances: allof0_sids,
-        cost_ceil: 10,
+        cost_ceil: Money::from(10),
+        ..Default::default()
     };
 
     let mut allof1_sids = HashMap::new();
 
-    allof1_sids.insert("abcde-0".into(), false);
+    allof1_sids.insert(ServiceCall::new("abcde", "0"), false);
 
     let allof1 = AllOf {
         service_instances: allof1_sids,
-        cost_ceil: 20,
+        cost_ceil: Money::from(20),
+        ..Default::default()
     };
 
     let mut allof2_sids = HashMap::new();
 
-    allof2_sids.insert("abcde-0".into(), false);
+    allof2_sids.insert(ServiceCall::new("abcde", "0"), false);
 
     let allof2 = AllOf {
         service_instances: allof2_sids,
-        cost_ceil: 20,
+        cost_ceil: Money::from(20),
+        ..Default::default()
     };
 
     let commit1 = Commit {
         allofs: vec![allof0],
+        ..Default::default()
     };
     let commit2 = Commit {
         allofs: vec![allof1],
+        ..Default::default()
     };
     let commit3 = Commit {
         allofs: vec![allof2],
+        ..Default::default()
     };
 
     vec![commit1, commit2, commit3]
@@ -67,44 +79,51 @@ fn build_unsat1() -> Vec<Commit> {
 
 fn build_sat1() -> Vec<Commit> {
     let mut allof0_sids = HashMap::new();
-    allof0_sids.insert("abcde-0".into(), true);
+    allof0_sids.insert(ServiceCall::new("abcde", "0"), true);
 
     let mut allof1_sids = HashMap::new();
-    allof1_sids.insert("efgh-0".into(), true);
+    allof1_sids.insert(ServiceCall::new("efgh", "0"), true);
 
     let allof0 = AllOf {
         service_instances: allof0_sids,
-        cost_ceil: 100,
+        cost_ceil: Money::from(100),
+        ..Default::default()
     };
 
     let allof1 = AllOf {
         service_instances: allof1_sids,
-        cost_ceil: 100,
+        cost_ceil: Money::from(100),
+        ..Default::default()
     };
 
     let commit1 = Commit {
         allofs: vec![allof0, allof1],
+        ..Default::default()
     };
 
     let mut allof2_sids = HashMap::new();
-    allof2_sids.insert("abcde-0".into(), false);
+    allof2_sids.insert(ServiceCall::new("abcde", "0"), false);
 
     let allof2 = AllOf {
         service_instances: allof2_sids,
-        cost_ceil: -50,
+        cost_ceil: Money::from(-50),
+        ..Default::default()
     };
     let commit2 = Commit {
         allofs: vec![allof2],
+        ..Default::default()
     };
 
     let mut allof3_sids = HashMap::new();
-    allof3_sids.insert("abcde-0".into(), false);
+    allof3_sids.insert(ServiceCall::new("abcde", "0"), false);
     let allof3 = AllOf {
         service_instances: allof3_sids,
-        cost_ceil: -50,
+        cost_ceil: Money::from(-50),
+        ..Default::default()
     };
     let commit3 = Commit {
         allofs: vec![allof3],
+        ..Default::default()
     };
 
     vec![commit1, commit2, commit3]
@@ -113,9 +132,49 @@ fn build_sat1() -> Vec<Commit> {
 fn main() {
     let unsat1 = build_unsat1();
     println!("{:#?}", unsat1);
-    build_schedule(unsat1);
+    match explain_unsat(&unsat1, &Market::default()) {
+        Ok(Some(core)) => print!("{}", core),
+        Ok(None) => {}
+        Err(err) => eprintln!("{}", err),
+    }
+    report(build_schedule(unsat1));
 
     let sat1 = build_sat1();
     println!("{:#?}", sat1);
-    build_schedule(sat1);
+    // commits 1 and 2 compete for the single request in commit 0
+    match explain_exclusion(
+        &sat1,
+        &Market::default(),
+        &ScheduleOptions::default(),
+        CommitId(2),
+    ) {
+        Ok(exclusion) => print!("{}", exclusion),
+        Err(err) => eprintln!("{}", err),
+    }
+    report(build_schedule(sat1));
+
+    // same commitments, arriving one at a time
+    let cfg = Config::new();
+    let ctx = Context::new(&cfg);
+    let mut scheduler = Scheduler::new(&ctx);
+    for commit in build_sat1() {
+        let added = scheduler.add_commit(commit);
+        println!("added {:?}", added);
+        report(scheduler.solve());
+    }
+    report(
+        scheduler
+            .withdraw_commit(CommitId(1))
+            .and_then(|_| scheduler.solve()),
+    );
+}
+
+fn report(result: Result<Schedule, ScheduleError>) {
+    match result {
+        Ok(schedule) => println!("{:#?}", schedule),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        }
+    }
 }
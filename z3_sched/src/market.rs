@@ -0,0 +1,232 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Market-wide input that is not part of any single commitment.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use ids::CommitId;
+use money::Money;
+use schedule::Schedule;
+use Commit;
+
+/// Everything the schedule depends on besides the commitments themselves
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Market {
+    /// party id -> wallet, shared by every commitment naming that party
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parties: BTreeMap<String, Party>,
+
+    /// constraints between commitments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Link>,
+
+    /// service regid -> bounds on its price
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prices: BTreeMap<String, PriceBounds>,
+
+    #[serde(default, skip_serializing_if = "MarketRules::is_default")]
+    pub rules: MarketRules,
+}
+
+impl Market {
+    /// Updates `Party::rounds_waiting` after a round that scheduled `schedule`
+    /// out of `commits`. Parties without commitments this round keep their
+    /// count.
+    pub fn record_round(&mut self, schedule: &Schedule, commits: &[Commit]) {
+        let mut submitted = BTreeSet::new();
+        let mut scheduled = BTreeSet::new();
+        for (i, commit) in commits.iter().enumerate() {
+            if let Some(ref party) = commit.party {
+                submitted.insert(party.as_str());
+                if schedule.commits.contains(&CommitId(i)) {
+                    scheduled.insert(party.as_str());
+                }
+            }
+        }
+
+        for party in submitted {
+            if let Some(wallet) = self.parties.get_mut(party) {
+                wallet.rounds_waiting = if scheduled.contains(party) {
+                    0
+                } else {
+                    wallet.rounds_waiting.saturating_add(1)
+                };
+            }
+        }
+    }
+}
+
+/// Bounds on the price of one service regid, whichever allofs it is traded in
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriceBounds {
+    /// reserve price the providers never sell below
+    #[serde(default, alias = "reserve", skip_serializing_if = "Option::is_none")]
+    pub min: Option<Money>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<Money>,
+
+    /// the price is a whole multiple of this, in place of the tick of
+    /// `Pricing::Tick`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick: Option<Money>,
+
+    /// the only prices allowed, if any are given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub levels: Vec<Money>,
+}
+
+/// The policy choices of the encoding that differ between marketplaces. The
+/// defaults are the rules the scheduler always had.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarketRules {
+    /// least number of scheduled commitments, 0 allows the empty schedule
+    pub min_commits: u32,
+
+    /// offers may be scheduled without a request taking them, and units
+    /// offered beyond those requested. What is left unsold earns nothing.
+    pub allow_unsold_offers: bool,
+
+    /// requests may be scheduled without an offer serving them, and units
+    /// requested beyond those offered. What is left unserved costs nothing.
+    pub allow_unserved_requests: bool,
+
+    /// prices without a lower bound in `Market::prices` may go below zero
+    pub allow_negative_prices: bool,
+
+    /// the numbers prices are drawn from
+    pub pricing: Pricing,
+}
+
+impl Default for MarketRules {
+    fn default() -> MarketRules {
+        MarketRules {
+            min_commits: 1,
+            allow_unsold_offers: false,
+            allow_unserved_requests: false,
+            allow_negative_prices: false,
+            pricing: Pricing::Integer,
+        }
+    }
+}
+
+impl MarketRules {
+    pub fn is_default(&self) -> bool {
+        *self == MarketRules::default()
+    }
+}
+
+/// The domain of the price variables. Integer pricing alone can make a market
+/// infeasible that clears at fractional prices, e.g. two buyers splitting a
+/// cost of 3 units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pricing {
+    /// whole units of `Money`, multiples of `money::SCALE` minor units
+    Integer,
+    /// any rational number of minor units, solved over the reals
+    Rational,
+    /// whole multiples of the given tick
+    Tick(Money),
+}
+
+impl Pricing {
+    pub fn is_rational(self) -> bool {
+        self == Pricing::Rational
+    }
+}
+
+/// One participant's wallet. Bounds the net cost summed over every scheduled
+/// allof of every commitment the party submitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Party {
+    /// most the party pays in total
+    pub budget: Money,
+
+    /// least a selling party earns in total, if scheduled at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_revenue: Option<Money>,
+
+    /// rounds in a row the party submitted commitments and had none
+    /// scheduled, the priority of round-robin tie-breaking
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rounds_waiting: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// A dependency between commitments, typically submitted by one party for
+/// related deals
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Link {
+    /// `commit` is only scheduled along with `on`
+    Requires { commit: CommitId, on: CommitId },
+    /// at most one of `commits` is scheduled
+    Excludes { commits: Vec<CommitId> },
+    /// either every one of `commits` is scheduled or none is
+    AllOrNone { commits: Vec<CommitId> },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn party(rounds_waiting: u32) -> Party {
+        Party {
+            budget: Money::from(10),
+            min_revenue: None,
+            rounds_waiting,
+        }
+    }
+
+    fn commit_by(party: &str) -> Commit {
+        Commit {
+            party: Some(party.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rounds_waiting_counts_rounds_without_a_scheduled_commitment() {
+        let mut market = Market::default();
+        market.parties.insert("served".into(), party(3));
+        market.parties.insert("waiting".into(), party(1));
+        market.parties.insert("absent".into(), party(2));
+
+        // "served" gets one of its two commitments through
+        let commits = vec![
+            commit_by("served"),
+            commit_by("waiting"),
+            commit_by("served"),
+        ];
+        let mut schedule = Schedule::sat();
+        schedule.commits.push(CommitId(2));
+
+        market.record_round(&schedule, &commits);
+
+        assert_eq!(market.parties["served"].rounds_waiting, 0);
+        assert_eq!(market.parties["waiting"].rounds_waiting, 2);
+        assert_eq!(market.parties["absent"].rounds_waiting, 2);
+    }
+}
@@ -0,0 +1,395 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Fixed-point amounts of money.
+//!
+//! Amounts are counted in micro-units, the `AMT = 10**6` convention of
+//! `sched_benchmarks/rounding.py`, so that cents and finer fractions are exact.
+//! In json an amount is a decimal string such as `"12.5"`, or an integer number
+//! of whole units.
+//!
+//! Under rational pricing the solver may settle on prices that are no whole
+//! number of minor units; those are reported exactly, as a `Price` written
+//! `"1/3"`.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+/// Minor units in one unit of currency
+pub const SCALE: i64 = 1_000_000;
+
+/// Decimal digits of `SCALE`
+const SCALE_DIGITS: usize = 6;
+
+/// An amount of money in minor units of `SCALE`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub fn zero() -> Money {
+        Money(0)
+    }
+
+    pub fn from_minor(minor: i64) -> Money {
+        Money(minor)
+    }
+
+    /// `units` whole units, `None` on overflow
+    pub fn from_units(units: i64) -> Option<Money> {
+        units.checked_mul(SCALE).map(Money)
+    }
+
+    /// The amount in minor units, as the solver sees it
+    pub fn minor(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Option<Money> {
+        self.0.checked_mul(factor).map(Money)
+    }
+
+    pub fn checked_neg(self) -> Option<Money> {
+        self.0.checked_neg().map(Money)
+    }
+}
+
+/// Whole units. An `i32` number of units always fits.
+impl From<i32> for Money {
+    fn from(units: i32) -> Money {
+        Money(i64::from(units) * SCALE)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = (i128::from(self.0)).abs();
+        let units = abs / i128::from(SCALE);
+        let fraction = abs % i128::from(SCALE);
+
+        if fraction == 0 {
+            return write!(f, "{}{}", sign, units);
+        }
+
+        let digits = format!("{:0width$}", fraction, width = SCALE_DIGITS);
+        write!(f, "{}{}.{}", sign, units, digits.trim_end_matches('0'))
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Money, String> {
+        let invalid = || format!("not an amount of money: {:?}", s);
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (units, fraction) = match digits.find('.') {
+            Some(pos) => (&digits[..pos], &digits[pos + 1..]),
+            None => (digits, ""),
+        };
+
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if units.is_empty() || !all_digits(units) || !all_digits(fraction) {
+            return Err(invalid());
+        }
+        if fraction.len() > SCALE_DIGITS {
+            return Err(format!(
+                "{} has more than {} decimal places",
+                s, SCALE_DIGITS
+            ));
+        }
+
+        let overflow = || format!("amount {} is out of range", s);
+        let units: i64 = units.parse().map_err(|_| overflow())?;
+        let mut minor = units.checked_mul(SCALE).ok_or_else(overflow)?;
+        if !fraction.is_empty() {
+            let padded = format!("{:0<width$}", fraction, width = SCALE_DIGITS);
+            let fraction: i64 = padded.parse().map_err(|_| invalid())?;
+            minor = minor.checked_add(fraction).ok_or_else(overflow)?;
+        }
+
+        Ok(Money(if negative { -minor } else { minor }))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+struct MoneyVisitor;
+
+impl<'de> Visitor<'de> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal string or an integer number of units")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Money, E> {
+        s.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, units: i64) -> Result<Money, E> {
+        Money::from_units(units)
+            .ok_or_else(|| E::custom(format!("amount {} is out of range", units)))
+    }
+
+    fn visit_u64<E: de::Error>(self, units: u64) -> Result<Money, E> {
+        if units > i64::MAX as u64 {
+            return Err(E::custom(format!("amount {} is out of range", units)));
+        }
+
+        self.visit_i64(units as i64)
+    }
+}
+
+/// An exact fraction `numer / denom`, kept in lowest terms with a positive
+/// denominator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fraction {
+    numer: i64,
+    denom: i64,
+}
+
+impl Fraction {
+    /// `None` if `denom` is zero or the reduced fraction is out of range
+    pub fn new(numer: i64, denom: i64) -> Option<Fraction> {
+        if denom == 0 {
+            return None;
+        }
+
+        let divisor = gcd(numer, denom);
+        let (mut numer, mut denom) = (i128::from(numer) / divisor, i128::from(denom) / divisor);
+        if denom < 0 {
+            numer = -numer;
+            denom = -denom;
+        }
+
+        let in_range = |n: i128| i128::from(i64::MIN) <= n && n <= i128::from(i64::MAX);
+        if !in_range(numer) || !in_range(denom) {
+            return None;
+        }
+
+        Some(Fraction {
+            numer: numer as i64,
+            denom: denom as i64,
+        })
+    }
+
+    pub fn from_integer(n: i64) -> Fraction {
+        Fraction { numer: n, denom: 1 }
+    }
+
+    pub fn numer(self) -> i64 {
+        self.numer
+    }
+
+    pub fn denom(self) -> i64 {
+        self.denom
+    }
+
+    pub fn to_integer(self) -> Option<i64> {
+        if self.denom == 1 {
+            Some(self.numer)
+        } else {
+            None
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i128 {
+    let (mut a, mut b) = (i128::from(a).abs(), i128::from(b).abs());
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+
+    a
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.denom == 1 {
+            write!(f, "{}", self.numer)
+        } else {
+            write!(f, "{}/{}", self.numer, self.denom)
+        }
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> Ordering {
+        let lhs = i128::from(self.numer) * i128::from(other.denom);
+        let rhs = i128::from(other.numer) * i128::from(self.denom);
+        lhs.cmp(&rhs)
+    }
+}
+
+/// Whole values as json numbers, anything else as a `"numer/denom"` string
+impl Serialize for Fraction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.to_integer() {
+            Some(n) => serializer.serialize_i64(n),
+            None => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+/// A solved price, in minor units of `Money`. Only rational pricing yields
+/// prices that are no whole number of minor units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(Fraction);
+
+impl Price {
+    pub fn new(minor: Fraction) -> Price {
+        Price(minor)
+    }
+
+    /// The price in minor units
+    pub fn minor(self) -> Fraction {
+        self.0
+    }
+
+    /// `None` for a fraction of a minor unit
+    pub fn to_money(self) -> Option<Money> {
+        self.0.to_integer().map(Money)
+    }
+}
+
+impl From<Money> for Price {
+    fn from(amount: Money) -> Price {
+        Price(Fraction::from_integer(amount.0))
+    }
+}
+
+/// As `Money` when it is a whole number of minor units, otherwise the exact
+/// fraction of units such as `1/3`
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(amount) = self.to_money() {
+            return write!(f, "{}", amount);
+        }
+
+        let units = i128::from(self.0.denom) * i128::from(SCALE);
+        let divisor = gcd(self.0.numer, SCALE);
+        write!(
+            f,
+            "{}/{}",
+            i128::from(self.0.numer) / divisor,
+            units / divisor
+        )
+    }
+}
+
+impl Serialize for Price {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn money_parses_decimal_strings() {
+        assert_eq!("12".parse(), Ok(Money::from_minor(12_000_000)));
+        assert_eq!("0.5".parse(), Ok(Money::from_minor(500_000)));
+        assert_eq!("-1.000001".parse(), Ok(Money::from_minor(-1_000_001)));
+        assert!("1.0000001".parse::<Money>().is_err());
+        assert!(".5".parse::<Money>().is_err());
+        assert!("1e3".parse::<Money>().is_err());
+        assert!("99999999999999".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn money_displays_without_trailing_zeros() {
+        assert_eq!(Money::from(3).to_string(), "3");
+        assert_eq!(Money::from_minor(1_500_000).to_string(), "1.5");
+        assert_eq!(Money::from_minor(-250).to_string(), "-0.00025");
+
+        let amount = Money::from_minor(-7_654_321);
+        assert_eq!(amount.to_string().parse(), Ok(amount));
+    }
+
+    #[test]
+    fn fractions_are_kept_in_lowest_terms() {
+        let half = Fraction::new(-2, -4).unwrap();
+        assert_eq!((half.numer(), half.denom()), (1, 2));
+
+        let third = Fraction::new(2, -6).unwrap();
+        assert_eq!((third.numer(), third.denom()), (-1, 3));
+        assert_eq!(third.to_string(), "-1/3");
+
+        assert_eq!(Fraction::new(6, 3).unwrap().to_integer(), Some(2));
+        assert_eq!(Fraction::new(6, 3).unwrap().to_string(), "2");
+        assert_eq!(Fraction::new(1, 0), None);
+        assert_eq!(Fraction::new(i64::MIN, -1), None);
+    }
+
+    #[test]
+    fn fractions_order_by_value() {
+        let third = Fraction::new(1, 3).unwrap();
+        let half = Fraction::new(1, 2).unwrap();
+        assert!(third < half);
+        assert!(Fraction::new(-1, 2).unwrap() < Fraction::from_integer(0));
+        assert_eq!(Fraction::new(2, 4).unwrap().cmp(&half), Ordering::Equal);
+    }
+
+    #[test]
+    fn prices_display_as_money_or_fractions_of_units() {
+        let third = Price::new(Fraction::new(1_000_000, 3).unwrap());
+        assert_eq!(third.to_string(), "1/3");
+        assert_eq!(third.to_money(), None);
+
+        let whole = Price::new(Fraction::from_integer(2_500_000));
+        assert_eq!(whole.to_string(), "2.5");
+        assert_eq!(whole.to_money(), Some(Money::from_minor(2_500_000)));
+    }
+}
@@ -0,0 +1,120 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use z3::Config;
+
+/// What a schedule is optimized for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Objective {
+    /// weighted number of scheduled commitments, tier by tier
+    #[default]
+    Commitments,
+    /// total slack `cost_ceil - net cost` over the scheduled allofs, in minor
+    /// units of `Money`
+    Surplus,
+    /// `Commitments`, then `Surplus` among the schedules tied on it
+    CommitmentsThenSurplus,
+}
+
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Objective, String> {
+        match s {
+            "commitments" => Ok(Objective::Commitments),
+            "surplus" => Ok(Objective::Surplus),
+            "commitments-then-surplus" => Ok(Objective::CommitmentsThenSurplus),
+            _ => Err(format!("unknown objective {}", s)),
+        }
+    }
+}
+
+/// How to choose among schedules the objective rates equally good. Without a
+/// policy the choice falls to commitment order and the solver's heuristics,
+/// which tend to disfavor the same commitments run after run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// whichever schedule the solver finds
+    #[default]
+    Solver,
+    /// a pseudo-random priority per commitment, drawn anew for each seed
+    Lottery { seed: u64 },
+    /// favor the parties that have gone unscheduled for the most rounds, see
+    /// `Market::record_round`
+    RoundRobin,
+    /// maximize the scheduled commitments of the worst-off party, then of the
+    /// next worst-off, and so on (leximin)
+    MaxMin,
+}
+
+/// `lottery` parses with seed 0; set the seed for a different draw
+impl FromStr for TieBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TieBreak, String> {
+        match s {
+            "solver" => Ok(TieBreak::Solver),
+            "lottery" => Ok(TieBreak::Lottery { seed: 0 }),
+            "round-robin" => Ok(TieBreak::RoundRobin),
+            "max-min" => Ok(TieBreak::MaxMin),
+            _ => Err(format!("unknown tie-breaking policy {}", s)),
+        }
+    }
+}
+
+/// Knobs for a single `build_schedule_with` call
+#[derive(Debug, Clone, Default)]
+pub struct ScheduleOptions {
+    pub objective: Objective,
+
+    /// the last objectives, deciding among schedules tied on `objective`
+    pub tie_break: TieBreak,
+
+    /// wall clock budget for the optimization, which the optimizer is
+    /// stopped at
+    pub timeout: Option<Duration>,
+
+    /// Z3 resource limit of the optimization
+    pub rlimit: Option<u64>,
+}
+
+impl ScheduleOptions {
+    pub fn has_limits(&self) -> bool {
+        self.timeout.is_some() || self.rlimit.is_some()
+    }
+
+    /// Z3 configuration carrying the limits. The optimizer of a context made
+    /// from it stops at either limit and keeps the best model found so far.
+    pub fn config(&self) -> Config {
+        let mut cfg = Config::new();
+
+        if let Some(timeout) = self.timeout {
+            let msec = timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis());
+            cfg.set_timeout_msec(msec);
+        }
+        if let Some(rlimit) = self.rlimit {
+            cfg.set_param_value("rlimit", &rlimit.to_string());
+        }
+
+        cfg
+    }
+}
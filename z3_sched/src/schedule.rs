@@ -0,0 +1,139 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::BTreeMap;
+
+use ids::{AllOfId, CommitId, ServiceCall};
+use money::{Fraction, Price};
+
+/// Outcome of the solver call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Sat,
+    Unsat,
+    /// the solver was interrupted before proving the schedule optimal (or the
+    /// system unsat)
+    Unknown {
+        reason: UnknownReason,
+    },
+}
+
+/// Why the solver gave up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownReason {
+    Timeout,
+    ResourceLimit,
+}
+
+/// A service-call instance that is part of a scheduled allof
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledServiceCall {
+    pub allof: AllOfId,
+    pub service_call: ServiceCall,
+    pub is_request: bool,
+}
+
+/// Units of a service requested or offered by a scheduled allof
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledQuantity {
+    pub allof: AllOfId,
+    pub service: String,
+    pub quantity: u32,
+    /// units matched with the other side and paid for, less than `quantity`
+    /// only where the market rules leave units unsold or unserved
+    pub traded: u32,
+    pub is_request: bool,
+}
+
+/// A scheduled request together with the offer serving it
+#[derive(Debug, Clone, Serialize)]
+pub struct Match {
+    pub service_call: ServiceCall,
+    /// allof requesting the service call
+    pub request: AllOfId,
+    /// allof offering it
+    pub offer: AllOfId,
+}
+
+/// Result of `build_schedule`.
+///
+/// For `Unsat` only `status` is meaningful. For `Unknown` the other fields hold
+/// the best schedule found before the solver was interrupted, and are empty
+/// if none was found.
+#[derive(Debug, Clone, Serialize)]
+pub struct Schedule {
+    pub status: Status,
+
+    /// the scheduled commitments
+    pub commits: Vec<CommitId>,
+
+    /// commit -> index of the allof chosen for it
+    pub allofs: BTreeMap<CommitId, usize>,
+
+    pub service_calls: Vec<ScheduledServiceCall>,
+
+    pub quantities: Vec<ScheduledQuantity>,
+
+    /// who serves whom
+    pub matches: Vec<Match>,
+
+    /// service-call regid -> price, exact even under rational pricing
+    pub prices: BTreeMap<String, Price>,
+
+    /// value of each objective, most important first
+    pub objective: Vec<Fraction>,
+}
+
+impl Schedule {
+    pub fn sat() -> Schedule {
+        Schedule::with_status(Status::Sat)
+    }
+
+    pub fn unsat() -> Schedule {
+        Schedule::with_status(Status::Unsat)
+    }
+
+    /// Labels the best schedule found so far as unknown
+    pub fn unknown(reason: UnknownReason, best_so_far: Option<Schedule>) -> Schedule {
+        let status = Status::Unknown { reason };
+
+        match best_so_far {
+            Some(mut schedule) => {
+                schedule.status = status;
+                schedule
+            }
+            None => Schedule::with_status(status),
+        }
+    }
+
+    fn with_status(status: Status) -> Schedule {
+        Schedule {
+            status,
+            commits: Vec::new(),
+            allofs: BTreeMap::new(),
+            service_calls: Vec::new(),
+            quantities: Vec::new(),
+            matches: Vec::new(),
+            prices: BTreeMap::new(),
+            objective: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{HashMap, HashSet};
+
+use z3::*;
+
+use error::ScheduleError;
+use ids::{AllOfId, CommitId, Constraint, ServiceCall, ServiceCallId, VarId};
+use options::{Objective, ScheduleOptions, TieBreak};
+use schedule::Schedule;
+use {run_optimizer, Commit, Encoding, Market};
+
+/// Constraints spanning commitments that are rebuilt together when the
+/// commitments or market rules they are about change
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Span {
+    /// request/offer matching of a service instance or wildcard
+    Matching(ServiceCall),
+    /// units requested and offered of a service
+    Balance(String),
+    /// budget and minimum revenue of a party
+    Party(String),
+    /// a link, by its index in the market
+    Link(usize),
+    /// floor, cap, tick and levels of the price of a service
+    Price(String),
+    MinCommits,
+}
+
+/// A scheduling session that keeps its Z3 context and solver alive while
+/// commitments come and go.
+///
+/// Every constraint is asserted once. Constraints that only concern one
+/// commitment are guarded by the commitment's live literal and asserted when
+/// it is added. Constraints spanning commitments, such as the matching of a
+/// service call or a party's budget, are guarded by a version literal of their
+/// span: when an added commitment or a new market changes them, a new version
+/// is asserted and the old literal is asserted false for good. `solve` assumes
+/// the live and current version literals inside a push/pop scope. Withdrawn
+/// commitments stay encoded, their live literal is simply assumed false.
+pub struct Scheduler<'ctx> {
+    ctx: &'ctx Context,
+    encoding: Encoding<'ctx>,
+    solver: Optimize<'ctx>,
+
+    /// commit -> (assumption literal, still live)
+    live: HashMap<CommitId, (Ast<'ctx>, bool)>,
+    /// constraints of the added commitments, guarded by their live literal
+    commit_constraints: Vec<(Constraint, Ast<'ctx>)>,
+
+    /// span -> (version literal, the constraints it guards)
+    versions: HashMap<Span, (Ast<'ctx>, Vec<(Constraint, Ast<'ctx>)>)>,
+    /// spans whose current version is out of date
+    stale: HashSet<Span>,
+
+    /// party budgets, links, price catalogue and rules
+    market: Market,
+
+    /// what `solve` maximizes, within which limits
+    options: ScheduleOptions,
+    /// objectives for `options`, rebuilt after `add_commit`
+    objectives: Option<Vec<Ast<'ctx>>>,
+}
+
+impl<'ctx> Scheduler<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> Scheduler<'ctx> {
+        Scheduler::with_options(ctx, ScheduleOptions::default())
+    }
+
+    /// A session whose `solve` calls optimize as `build_schedule_with` does
+    /// for `options`. The limits only apply if `ctx` was made from
+    /// `options.config()`.
+    pub fn with_options(ctx: &'ctx Context, options: ScheduleOptions) -> Scheduler<'ctx> {
+        Scheduler {
+            ctx,
+            encoding: Encoding::new(ctx),
+            solver: Optimize::new(ctx),
+            live: HashMap::new(),
+            commit_constraints: Vec::new(),
+            versions: HashMap::new(),
+            stale: HashSet::new(),
+            market: Market::default(),
+            options,
+            objectives: None,
+        }
+    }
+
+    /// Encodes `commit` and returns the id it is known by from now on
+    pub fn add_commit(&mut self, commit: Commit) -> Result<CommitId, ScheduleError> {
+        let commit_id = CommitId(self.live.len());
+
+        // services priced for the first time by this commitment
+        let mut new_prices = HashSet::new();
+        for allof in &commit.allofs {
+            let regids = allof.s_calls().into_iter().map(|(s_call, _)| &s_call.regid);
+            let services = allof.quantities.iter().map(|quantity| &quantity.service);
+            for regid in regids.chain(services) {
+                if !self.encoding.s_call_prices.contains_key(regid) {
+                    new_prices.insert(regid.clone());
+                }
+            }
+        }
+
+        let constraints = self.encoding.add_commit(commit_id, &commit)?;
+
+        let literal_name = self
+            .encoding
+            .names
+            .name(VarId::Assumption(Constraint::Live(commit_id)));
+        let literal = self.ctx.named_bool_const(literal_name.as_str());
+
+        // a withdrawn commitment neither constrains the shared variables nor
+        // schedules any of its own
+        let mut guarded: Vec<(Constraint, Ast<'ctx>)> = constraints
+            .into_iter()
+            .map(|(tag, constraint)| (tag, literal.implies(&constraint)))
+            .collect();
+        let mut own_vars = vec![&self.encoding.commit_bools[&commit_id]];
+        for (j, allof) in commit.allofs.iter().enumerate() {
+            let allof_id = AllOfId {
+                commit: commit_id,
+                allof: j,
+            };
+            own_vars.push(&self.encoding.allof_bools[&allof_id]);
+            for (s_call, _) in allof.s_calls() {
+                let s_call_id = ServiceCallId {
+                    allof: allof_id,
+                    call: s_call.clone(),
+                };
+                own_vars.push(&self.encoding.s_call_bools[&s_call_id].0);
+            }
+        }
+        for var in own_vars {
+            guarded.push((Constraint::Live(commit_id), var.implies(&literal)));
+        }
+
+        for (_, constraint) in &guarded {
+            self.solver.assert(constraint);
+        }
+        self.commit_constraints.extend(guarded);
+        self.live.insert(commit_id, (literal, true));
+
+        // the spans the commitment takes part in. Its links could not be
+        // built before it existed, so they are still stale.
+        for allof in &commit.allofs {
+            for (s_call, _) in allof.s_calls() {
+                self.stale.insert(Span::Matching(s_call.clone()));
+            }
+            for quantity in &allof.quantities {
+                self.stale.insert(Span::Balance(quantity.service.clone()));
+            }
+        }
+        if let Some(ref party) = commit.party {
+            self.stale.insert(Span::Party(party.clone()));
+        }
+        self.stale.extend(new_prices.into_iter().map(Span::Price));
+        self.stale.insert(Span::MinCommits);
+        self.objectives = None;
+
+        Ok(commit_id)
+    }
+
+    /// Replaces the party table, links, price catalogue and rules later
+    /// `solve` calls respect. Whether prices are rational can only change
+    /// before the first commitment, whose prices are built in that sort.
+    pub fn set_market(&mut self, market: Market) -> Result<(), ScheduleError> {
+        let was_rational = self.encoding.rules.pricing.is_rational();
+        if !self.live.is_empty() && market.rules.pricing.is_rational() != was_rational {
+            return Err(ScheduleError::InvalidInput(
+                "rational pricing can not change once commitments are added".into(),
+            ));
+        }
+
+        // the rules shape the constraints of every span, and links come and go
+        self.encoding.rules = market.rules.clone();
+        self.stale.extend(self.versions.keys().cloned());
+        self.stale.extend((0..market.links.len()).map(Span::Link));
+        self.objectives = None;
+
+        self.market = market;
+
+        Ok(())
+    }
+
+    /// Changes what later `solve` calls maximize
+    pub fn set_objective(&mut self, mode: Objective) {
+        self.options.objective = mode;
+        self.objectives = None;
+    }
+
+    /// Changes how later `solve` calls choose among equally good schedules
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.options.tie_break = tie_break;
+        self.objectives = None;
+    }
+
+    /// Keeps `commit_id` out of every later schedule
+    pub fn withdraw_commit(&mut self, commit_id: CommitId) -> Result<(), ScheduleError> {
+        match self.live.get_mut(&commit_id) {
+            Some(&mut (_, ref mut live)) if *live => {
+                *live = false;
+                Ok(())
+            }
+            Some(_) => Err(ScheduleError::InvalidInput(format!(
+                "{} is already withdrawn",
+                commit_id
+            ))),
+            None => Err(ScheduleError::InvalidInput(format!(
+                "{} does not exist",
+                commit_id
+            ))),
+        }
+    }
+
+    /// Maximizes the objective over the live commitments. A limit that
+    /// interrupts the optimization makes the schedule `Unknown`, see
+    /// `run_optimizer`.
+    pub fn solve(&mut self) -> Result<Schedule, ScheduleError> {
+        if !self.live.values().any(|&(_, live)| live) {
+            return Err(ScheduleError::InvalidInput("no live commitments".into()));
+        }
+
+        self.refresh()?;
+
+        if self.objectives.is_none() {
+            let mut objectives = self.encoding.objectives_for(self.options.objective);
+            objectives.extend(
+                self.encoding
+                    .tie_break_objective(self.options.tie_break, &self.market),
+            );
+            self.objectives = Some(objectives);
+        }
+        self.encoding.objectives = self.objectives.clone().unwrap_or_default();
+
+        let mut assumed = Vec::new();
+        for (commit_id, &(ref literal, live)) in &self.live {
+            let fact = if live { literal.clone() } else { literal.not() };
+            assumed.push((Constraint::Live(*commit_id), fact));
+        }
+
+        if self.options.has_limits() {
+            // what the model of an interrupted check must satisfy to be a
+            // schedule
+            let mut in_force = self.commit_constraints.clone();
+            in_force.extend(assumed.iter().cloned());
+            for (_, constraints) in self.versions.values() {
+                in_force.extend(constraints.iter().cloned());
+            }
+            self.encoding.constraints = in_force;
+        }
+
+        self.solver.push();
+
+        for (_, fact) in &assumed {
+            self.solver.assert(fact);
+        }
+        for (version, _) in self.versions.values() {
+            self.solver.assert(version);
+        }
+        for objective in &self.encoding.objectives {
+            self.solver.maximize(objective);
+        }
+
+        let result = run_optimizer(&self.solver, &self.encoding, &self.options);
+
+        self.solver.pop();
+
+        result
+    }
+
+    /// Asserts a new version of every stale span and retires the old ones.
+    /// All spans are built before anything is asserted, so an error leaves
+    /// the solver as it was.
+    fn refresh(&mut self) -> Result<(), ScheduleError> {
+        let mut rebuilt: HashMap<Span, Vec<(Constraint, Ast<'ctx>)>> = self
+            .stale
+            .iter()
+            .map(|span| (span.clone(), Vec::new()))
+            .collect();
+
+        let s_calls = self.stale.iter().filter_map(|span| match *span {
+            Span::Matching(ref s_call) => Some(s_call),
+            _ => None,
+        });
+        for s_call in self.encoding.matching_order(s_calls) {
+            let constraints = self.encoding.matching_constraints(&s_call);
+            rebuilt.insert(Span::Matching(s_call), constraints);
+        }
+
+        let mut services = HashSet::new();
+        let mut parties = HashSet::new();
+        let mut links = HashSet::new();
+        let mut regids = HashSet::new();
+        for span in &self.stale {
+            match *span {
+                Span::Balance(ref service) => services.insert(service.as_str()),
+                Span::Party(ref party) => parties.insert(party.as_str()),
+                Span::Link(i) => links.insert(i),
+                Span::Price(ref regid) => regids.insert(regid.as_str()),
+                Span::Matching(_) | Span::MinCommits => false,
+            };
+        }
+
+        let encoding = &self.encoding;
+        let market = &self.market;
+        let mut constraints = encoding.balance_constraints_of(|service| services.contains(service));
+        constraints.extend(encoding.party_constraints_of(market, |party| parties.contains(party))?);
+        constraints.extend(encoding.link_constraints_of(market, |i| links.contains(&i))?);
+        constraints.extend(encoding.price_constraints_of(market, |regid| regids.contains(regid))?);
+        for (tag, constraint) in constraints {
+            rebuilt
+                .entry(market_span(&tag))
+                .or_default()
+                .push((tag, constraint));
+        }
+
+        if self.stale.contains(&Span::MinCommits) {
+            let min_commits = market.rules.min_commits;
+            rebuilt.insert(
+                Span::MinCommits,
+                vec![(
+                    Constraint::MinCommits(min_commits),
+                    encoding.min_commits(min_commits),
+                )],
+            );
+        }
+
+        for (span, constraints) in rebuilt {
+            if let Some((retired, _)) = self.versions.remove(&span) {
+                self.solver.assert(&retired.not());
+            }
+            if let Span::Link(i) = span {
+                if i >= market.links.len() {
+                    continue;
+                }
+            }
+
+            // spans without constraints get a version too, so that a new
+            // market rebuilds them
+            let version = self.ctx.fresh_bool_const("v");
+            for (_, constraint) in &constraints {
+                self.solver.assert(&version.implies(constraint));
+            }
+            self.versions.insert(span, (version, constraints));
+        }
+        self.stale.clear();
+
+        Ok(())
+    }
+}
+
+/// The span a balance, party, link or price constraint belongs to
+fn market_span(tag: &Constraint) -> Span {
+    match *tag {
+        Constraint::Balance(ref service) => Span::Balance(service.clone()),
+        Constraint::Budget(ref party) | Constraint::MinRevenue(ref party) => {
+            Span::Party(party.clone())
+        }
+        Constraint::Link(i) => Span::Link(i),
+        Constraint::PriceFloor(ref regid)
+        | Constraint::PriceCap(ref regid)
+        | Constraint::PriceTick(ref regid)
+        | Constraint::PriceLevel(ref regid) => Span::Price(regid.clone()),
+        _ => unreachable!("{} is not a market constraint", tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use money::Fraction;
+    use schedule::{Status, UnknownReason};
+    use tests::two_sellers;
+
+    #[test]
+    fn commitments_arriving_and_leaving_are_rescheduled() {
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let mut scheduler = Scheduler::new(&ctx);
+        let mut commits = two_sellers().into_iter();
+
+        // the buyer alone has nobody to buy from
+        scheduler.add_commit(commits.next().unwrap()).unwrap();
+        assert_eq!(scheduler.solve().unwrap().status, Status::Unsat);
+
+        scheduler.add_commit(commits.next().unwrap()).unwrap();
+        let schedule = scheduler.solve().unwrap();
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(1)]);
+        assert_eq!(schedule.objective, vec![Fraction::from_integer(2)]);
+
+        // the second seller only ties, and takes over once the first leaves
+        scheduler.add_commit(commits.next().unwrap()).unwrap();
+        assert_eq!(scheduler.solve().unwrap().commits.len(), 2);
+        scheduler.withdraw_commit(CommitId(1)).unwrap();
+        let schedule = scheduler.solve().unwrap();
+        assert_eq!(schedule.commits, vec![CommitId(0), CommitId(2)]);
+
+        // a new market rebuilds the constraints spanning commitments
+        let mut market = Market::default();
+        market.rules.min_commits = 3;
+        scheduler.set_market(market.clone()).unwrap();
+        assert_eq!(scheduler.solve().unwrap().status, Status::Unsat);
+        market.rules.min_commits = 2;
+        scheduler.set_market(market).unwrap();
+        assert_eq!(scheduler.solve().unwrap().status, Status::Sat);
+    }
+
+    #[test]
+    fn an_interrupted_solve_is_unknown() {
+        let options = ScheduleOptions {
+            rlimit: Some(1),
+            ..Default::default()
+        };
+        let cfg = options.config();
+        let ctx = Context::new(&cfg);
+        let mut scheduler = Scheduler::with_options(&ctx, options);
+        for commit in two_sellers() {
+            scheduler.add_commit(commit).unwrap();
+        }
+
+        assert_eq!(
+            scheduler.solve().unwrap().status,
+            Status::Unknown {
+                reason: UnknownReason::ResourceLimit
+            }
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//  Copyright 2018- Onai (Onu Technology, Inc., San Jose, California)
+//
+//  Permission is hereby granted, free of charge, to any person obtaining a copy
+//  of this software and associated documentation files (the "Software"), to deal
+//  in the Software without restriction, including without limitation the rights
+//  to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//  copies of the Software, and to permit persons to whom the Software is furnished
+//  to do so, subject to the following conditions:
+
+//  The above copyright notice and this permission notice shall be included in all
+//   copies or substantial portions of the Software.
+
+//  THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+//   INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+//  PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+//  HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+//  OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+//  SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Who pays whom for a schedule.
+//!
+//! Every match is one payment: the request pays the price of its service
+//! regid and the offer serving it receives it, so an offer taken by several
+//! requests is paid by each. Requests nobody serves and offers nobody takes
+//! neither pay nor receive. Quantities are paid per unit traded, so units the
+//! market rules leave unsold or unserved are not paid for either.
+//!
+//! Amounts are signed from the payer's side: a positive amount is paid, a
+//! negative one received. Amounts are checked for overflow rather than
+//! wrapping.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use error::ScheduleError;
+use ids::{AllOfId, CommitId};
+use money::Money;
+use schedule::Schedule;
+use Commit;
+
+/// One side of a match or one scheduled quantity and what it costs its allof
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    pub allof: AllOfId,
+    /// submitting party of the commitment, if it names one
+    pub party: Option<String>,
+    pub regid: String,
+    /// the instance of a service call, `None` for a quantity
+    pub instance: Option<String>,
+    /// units paid for, 1 for a service call
+    pub quantity: u32,
+    pub is_request: bool,
+    pub amount: Money,
+}
+
+/// Net payments of a schedule
+#[derive(Debug, Clone, Serialize)]
+pub struct Settlement {
+    pub entries: Vec<LedgerEntry>,
+
+    /// scheduled commitment -> net payment
+    pub commits: BTreeMap<CommitId, Money>,
+
+    /// party -> net payment over all its scheduled commitments
+    pub parties: BTreeMap<String, Money>,
+}
+
+impl Settlement {
+    /// The ledger entries as CSV, one line per side of a match or scheduled
+    /// quantity
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("commit,allof,party,regid,instance,quantity,side,amount\n");
+
+        for entry in &self.entries {
+            let side = if entry.is_request { "request" } else { "offer" };
+            // writing to a String can not fail
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                entry.allof.commit.0,
+                entry.allof.allof,
+                csv_field(entry.party.as_ref().map_or("", |party| party.as_str())),
+                csv_field(&entry.regid),
+                csv_field(
+                    entry
+                        .instance
+                        .as_ref()
+                        .map_or("", |instance| instance.as_str())
+                ),
+                entry.quantity,
+                side,
+                entry.amount
+            );
+        }
+
+        csv
+    }
+}
+
+/// Settles `schedule`, which was built from `commits`
+pub fn settle(schedule: &Schedule, commits: &[Commit]) -> Result<Settlement, ScheduleError> {
+    let mut settlement = Settlement {
+        entries: Vec::new(),
+        commits: BTreeMap::new(),
+        parties: BTreeMap::new(),
+    };
+
+    for commit_id in &schedule.commits {
+        settlement.commits.insert(*commit_id, Money::zero());
+    }
+
+    for served in &schedule.matches {
+        for &(allof, is_request) in &[(served.request, true), (served.offer, false)] {
+            settlement.book(
+                LedgerEntry {
+                    allof,
+                    party: None,
+                    regid: served.service_call.regid.clone(),
+                    instance: Some(served.service_call.instance.clone()),
+                    quantity: 1,
+                    is_request,
+                    amount: Money::zero(),
+                },
+                schedule,
+                commits,
+            )?;
+        }
+    }
+
+    check_units(schedule)?;
+
+    for quantity in &schedule.quantities {
+        settlement.book(
+            LedgerEntry {
+                allof: quantity.allof,
+                party: None,
+                regid: quantity.service.clone(),
+                instance: None,
+                quantity: quantity.traded,
+                is_request: quantity.is_request,
+                amount: Money::zero(),
+            },
+            schedule,
+            commits,
+        )?;
+    }
+
+    let mut total = Money::zero();
+    for amount in settlement.commits.values() {
+        add_to(&mut total, *amount)
+            .ok_or_else(|| ScheduleError::InvalidInput("payments overflow".into()))?;
+    }
+    if total != Money::zero() {
+        return Err(ScheduleError::Encoding(format!(
+            "payments do not balance, {} is left over",
+            total
+        )));
+    }
+
+    Ok(settlement)
+}
+
+/// Every service traded in quantities has as many units sold as bought
+fn check_units(schedule: &Schedule) -> Result<(), ScheduleError> {
+    let mut units: BTreeMap<&str, i64> = BTreeMap::new();
+    for quantity in &schedule.quantities {
+        let entry = units.entry(quantity.service.as_str()).or_insert(0);
+        if quantity.is_request {
+            *entry += i64::from(quantity.traded);
+        } else {
+            *entry -= i64::from(quantity.traded);
+        }
+    }
+
+    if let Some((service, _)) = units.into_iter().find(|&(_, excess)| excess != 0) {
+        return Err(ScheduleError::InvalidInput(format!(
+            "units of {} sold and bought differ, so their payments do not balance",
+            service
+        )));
+    }
+
+    Ok(())
+}
+
+impl Settlement {
+    /// Fills in the party and amount of `entry` and books it
+    fn book(
+        &mut self,
+        mut entry: LedgerEntry,
+        schedule: &Schedule,
+        commits: &[Commit],
+    ) -> Result<(), ScheduleError> {
+        let commit_id = entry.allof.commit;
+        let commit = commits.get(commit_id.0).ok_or_else(|| {
+            ScheduleError::InvalidInput(format!("{} is not in the input", commit_id))
+        })?;
+        let price = schedule.prices.get(&entry.regid).ok_or_else(|| {
+            ScheduleError::Encoding(format!("no price for service {}", entry.regid))
+        })?;
+        let price = price.to_money().ok_or_else(|| {
+            ScheduleError::InvalidInput(format!(
+                "price {} of {} is a fraction of a minor unit and can not be settled",
+                price, entry.regid
+            ))
+        })?;
+
+        let overflow =
+            || ScheduleError::InvalidInput(format!("payments of {} overflow", commit_id));
+
+        let cost = price
+            .checked_mul(i64::from(entry.quantity))
+            .ok_or_else(overflow)?;
+        entry.amount = if entry.is_request {
+            cost
+        } else {
+            cost.checked_neg().ok_or_else(overflow)?
+        };
+        entry.party = commit.party.clone();
+
+        add_to(
+            self.commits.entry(commit_id).or_insert_with(Money::zero),
+            entry.amount,
+        )
+        .ok_or_else(overflow)?;
+        if let Some(ref party) = entry.party {
+            add_to(
+                self.parties
+                    .entry(party.clone())
+                    .or_insert_with(Money::zero),
+                entry.amount,
+            )
+            .ok_or_else(overflow)?;
+        }
+
+        self.entries.push(entry);
+
+        Ok(())
+    }
+}
+
+/// `*total += amount`, `None` on overflow
+fn add_to(total: &mut Money, amount: Money) -> Option<()> {
+    *total = total.checked_add(amount)?;
+
+    Some(())
+}
+
+/// Quotes `field` if it would otherwise break the CSV line
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("abcde"), "abcde");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}